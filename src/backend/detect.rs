@@ -0,0 +1,277 @@
+use std::fmt;
+
+use super::{netplan, Backend};
+use crate::error::Error;
+use crate::resolv_conf::{ResolvConf, RESOLV_CONF_PATH};
+use crate::system::System;
+
+const RESOLVCONF_DIR: &str = "/etc/resolvconf";
+
+/// Daemons worth knowing about, as `(comm, name)`: the kernel truncates
+/// process names to 15 bytes.
+const DAEMONS: &[(&str, &str)] = &[
+    ("systemd-resolve", "systemd-resolved"),
+    ("NetworkManager", "NetworkManager"),
+    ("dnsmasq", "dnsmasq"),
+    ("unbound", "unbound"),
+    ("dhclient", "dhclient"),
+    ("dhcpcd", "dhcpcd"),
+];
+
+/// The backend whose configuration actually takes effect, and what it was
+/// chosen from.
+pub struct Detection {
+    pub backend: &'static dyn Backend,
+    pub reasons: Vec<String>,
+}
+
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Detected backend: {}", self.backend.name())?;
+        for reason in &self.reasons {
+            write!(f, "\n    {}", reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Looks at who owns /etc/resolv.conf and which daemons are running to tell
+/// which backend to use.
+pub fn detect(system: &System) -> Result<Detection, Error> {
+    let mut reasons = Vec::new();
+
+    let target = system
+        .read_link(RESOLV_CONF_PATH)?
+        .map(|target| target.to_string_lossy().into_owned());
+    let content = system.read(RESOLV_CONF_PATH)?.unwrap_or_default();
+    match &target {
+        Some(target) => reasons.push(format!("{} is a symlink to {}", RESOLV_CONF_PATH, target)),
+        None if content.is_empty() => reasons.push(format!("{} is missing", RESOLV_CONF_PATH)),
+        None => reasons.push(format!("{} is a regular file", RESOLV_CONF_PATH)),
+    }
+
+    let running = running_daemons(system)?;
+    for daemon in &running {
+        reasons.push(format!("{} is running", daemon));
+    }
+    let is_running = |name: &str| running.iter().any(|daemon| daemon == name);
+
+    let has_resolvconf = system.path(RESOLVCONF_DIR)?.is_dir();
+    if has_resolvconf {
+        reasons.push(format!("{} exists", RESOLVCONF_DIR));
+    }
+    let mut uses_netplan = false;
+    let netplan_files = netplan::config_files(system)?;
+    if !netplan_files.is_empty() {
+        reasons.push(format!("netplan is configured in {}", netplan::NETPLAN_DIR));
+        let mut renders_for_networkmanager = false;
+        for path in netplan_files {
+            let content = system.read(path)?.unwrap_or_default();
+            renders_for_networkmanager |= content
+                .lines()
+                .any(|line| line.trim() == "renderer: NetworkManager");
+        }
+        if renders_for_networkmanager {
+            reasons.push(String::from(
+                "netplan hands the interfaces to NetworkManager",
+            ));
+        }
+        uses_netplan = !renders_for_networkmanager;
+    }
+
+    let target = target.unwrap_or_default();
+    // netplan regenerates the per-link settings of networkd, and with them
+    // those of systemd-resolved, on every boot.
+    let name = if uses_netplan {
+        "netplan"
+    } else if target.contains("/run/systemd/resolve/")
+        || (is_running("systemd-resolved") && content.contains("nameserver 127.0.0.53"))
+    {
+        "systemd-resolved"
+    } else if is_running("dnsmasq") && is_loopback(&content) {
+        "dnsmasq"
+    } else if is_running("unbound") && is_loopback(&content) {
+        "unbound"
+    } else if target.contains("/run/resolvconf/") || target.contains("/etc/resolvconf/") {
+        "resolvconf"
+    } else if target.contains("/run/NetworkManager/")
+        || (is_running("NetworkManager") && content.starts_with("# Generated by NetworkManager"))
+    {
+        "networkmanager"
+    } else if has_resolvconf {
+        "resolvconf"
+    } else if is_running("dhcpcd") {
+        "dhcpcd"
+    } else if is_running("dhclient") {
+        "dhclient"
+    } else {
+        "resolv-conf"
+    };
+
+    Ok(Detection {
+        backend: super::find(name).expect("detected backends are registered"),
+        reasons,
+    })
+}
+
+/// Tells whether the first nameserver is a local cache.
+fn is_loopback(content: &str) -> bool {
+    ResolvConf::parse(content)
+        .nameservers()
+        .next()
+        .is_some_and(|addr| addr.is_loopback())
+}
+
+/// The known daemons found in /proc.
+fn running_daemons(system: &System) -> Result<Vec<String>, Error> {
+    let mut comms = Vec::new();
+    for path in system.read_dir("/proc")? {
+        let is_pid = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().bytes().all(|b| b.is_ascii_digit()));
+        if !is_pid {
+            continue;
+        }
+        // Processes may exit while /proc is being walked.
+        if let Ok(Some(comm)) = system.read(path.join("comm")) {
+            comms.push(comm.trim().to_string());
+        }
+    }
+
+    Ok(DAEMONS
+        .iter()
+        .filter(|(comm, _)| comms.iter().any(|running| running == comm))
+        .map(|(_, name)| name.to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    fn run(system: &System, pid: u32, comm: &str) {
+        system
+            .write(format!("/proc/{}/comm", pid), &format!("{}\n", comm))
+            .unwrap();
+    }
+
+    #[test]
+    fn stub_symlink_means_systemd_resolved() -> Result<(), Error> {
+        let root = TempDir::new("detect-resolved");
+        let system = System::with_root(root.path(), None);
+        fs::create_dir_all(root.path().join("etc"))?;
+        symlink(
+            "../run/systemd/resolve/stub-resolv.conf",
+            root.path().join("etc/resolv.conf"),
+        )?;
+        run(&system, 412, "systemd-resolve");
+        run(&system, 530, "NetworkManager");
+
+        let detection = detect(&system)?;
+
+        assert_eq!(detection.backend.name(), "systemd-resolved");
+        assert_eq!(
+            detection.reasons,
+            [
+                "/etc/resolv.conf is a symlink to ../run/systemd/resolve/stub-resolv.conf",
+                "systemd-resolved is running",
+                "NetworkManager is running",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn netplan_wins_unless_it_renders_for_networkmanager() -> Result<(), Error> {
+        let root = TempDir::new("detect-netplan");
+        let system = System::with_root(root.path(), None);
+        system.write(RESOLV_CONF_PATH, "nameserver 127.0.0.53\n")?;
+        run(&system, 412, "systemd-resolve");
+        system.write(
+            "/etc/netplan/50-cloud-init.yaml",
+            "network:\n  ethernets:\n    eth0:\n      dhcp4: true\n",
+        )?;
+
+        assert_eq!(detect(&system)?.backend.name(), "netplan");
+
+        system.write(
+            "/etc/netplan/01-network-manager-all.yaml",
+            "network:\n  version: 2\n  renderer: NetworkManager\n",
+        )?;
+
+        assert_eq!(detect(&system)?.backend.name(), "systemd-resolved");
+        Ok(())
+    }
+
+    #[test]
+    fn file_generated_by_networkmanager_means_networkmanager() -> Result<(), Error> {
+        let root = TempDir::new("detect-networkmanager");
+        let system = System::with_root(root.path(), None);
+        system.write(
+            RESOLV_CONF_PATH,
+            "# Generated by NetworkManager\nnameserver 192.168.1.1\n",
+        )?;
+        run(&system, 530, "NetworkManager");
+
+        assert_eq!(detect(&system)?.backend.name(), "networkmanager");
+        Ok(())
+    }
+
+    #[test]
+    fn resolvconf_is_used_when_installed() -> Result<(), Error> {
+        let root = TempDir::new("detect-resolvconf");
+        let system = System::with_root(root.path(), None);
+        system.write("/etc/resolvconf/resolv.conf.d/head", "")?;
+        fs::create_dir_all(root.path().join("etc"))?;
+        symlink(
+            "/run/resolvconf/resolv.conf",
+            root.path().join("etc/resolv.conf"),
+        )?;
+
+        assert_eq!(detect(&system)?.backend.name(), "resolvconf");
+        Ok(())
+    }
+
+    #[test]
+    fn plain_file_falls_back_to_resolv_conf() -> Result<(), Error> {
+        let root = TempDir::new("detect-plain");
+        let system = System::with_root(root.path(), None);
+        system.write(RESOLV_CONF_PATH, "nameserver 10.0.0.1\n")?;
+
+        let detection = detect(&system)?;
+
+        assert_eq!(detection.backend.name(), "resolv-conf");
+        assert_eq!(detection.reasons, ["/etc/resolv.conf is a regular file"]);
+        Ok(())
+    }
+
+    #[test]
+    fn local_cache_is_configured_when_it_is_used() -> Result<(), Error> {
+        let root = TempDir::new("detect-forwarder");
+        let system = System::with_root(root.path(), None);
+        system.write("/etc/resolvconf/resolv.conf.d/head", "")?;
+        system.write(RESOLV_CONF_PATH, "nameserver 127.0.0.1\n")?;
+        run(&system, 733, "dnsmasq");
+
+        assert_eq!(detect(&system)?.backend.name(), "dnsmasq");
+
+        system.write(RESOLV_CONF_PATH, "nameserver 192.168.1.1\n")?;
+
+        assert_eq!(detect(&system)?.backend.name(), "resolvconf");
+        Ok(())
+    }
+
+    #[test]
+    fn running_dhcp_client_rewrites_plain_file() -> Result<(), Error> {
+        let root = TempDir::new("detect-dhcp");
+        let system = System::with_root(root.path(), None);
+        system.write(RESOLV_CONF_PATH, "nameserver 10.0.0.1\n")?;
+        run(&system, 812, "dhclient");
+
+        assert_eq!(detect(&system)?.backend.name(), "dhclient");
+        Ok(())
+    }
+}
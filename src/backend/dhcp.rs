@@ -0,0 +1,244 @@
+use std::net::IpAddr;
+
+use super::{Backend, Configured};
+use crate::block;
+use crate::error::Error;
+use crate::provider::Profile;
+use crate::system::{Note, System};
+
+const DHCLIENT_CONF_PATH: &str = "/etc/dhcp/dhclient.conf";
+const DHCPCD_CONF_PATH: &str = "/etc/dhcpcd.conf";
+
+/// Makes ISC dhclient(8) supersede the nameservers offered by DHCP servers,
+/// with a managed block of dhclient.conf(5).
+pub struct Dhclient;
+
+/// Gives dhcpcd(8) static nameservers, with a managed block of
+/// dhcpcd.conf(5).
+pub struct Dhcpcd;
+
+impl Backend for Dhclient {
+    fn name(&self) -> &'static str {
+        "dhclient"
+    }
+
+    fn activate(&self, system: &System, profile: &Profile) -> Result<(), Error> {
+        let (v4, v6) = split(&profile.nameservers());
+        let mut body = format!("# {}\n# {}\n", profile.provider.label, profile.provider.url);
+        if !v4.is_empty() {
+            body.push_str(&format!(
+                "supersede domain-name-servers {};\n",
+                v4.join(", ")
+            ));
+        }
+        if !v6.is_empty() {
+            body.push_str(&format!(
+                "supersede dhcp6.name-servers {};\n",
+                v6.join(", ")
+            ));
+        }
+
+        activate(system, DHCLIENT_CONF_PATH, &body)?;
+        note_renewal(system, "dhclient -r && dhclient");
+        Ok(())
+    }
+
+    fn deactivate(&self, system: &System) -> Result<(), Error> {
+        deactivate(system, DHCLIENT_CONF_PATH)
+    }
+
+    fn configured(&self, system: &System) -> Result<Vec<Configured>, Error> {
+        configured(system, DHCLIENT_CONF_PATH)
+    }
+}
+
+impl Backend for Dhcpcd {
+    fn name(&self) -> &'static str {
+        "dhcpcd"
+    }
+
+    fn activate(&self, system: &System, profile: &Profile) -> Result<(), Error> {
+        let nameservers: Vec<_> = profile
+            .nameservers()
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect();
+        let body = format!(
+            "# {}\n# {}\nstatic domain_name_servers={}\n",
+            profile.provider.label,
+            profile.provider.url,
+            nameservers.join(" ")
+        );
+
+        activate(system, DHCPCD_CONF_PATH, &body)?;
+        note_renewal(system, "dhcpcd --rebind");
+        Ok(())
+    }
+
+    fn deactivate(&self, system: &System) -> Result<(), Error> {
+        deactivate(system, DHCPCD_CONF_PATH)
+    }
+
+    fn configured(&self, system: &System) -> Result<Vec<Configured>, Error> {
+        configured(system, DHCPCD_CONF_PATH)
+    }
+}
+
+fn split(nameservers: &[IpAddr]) -> (Vec<String>, Vec<String>) {
+    let (v4, v6): (Vec<_>, Vec<_>) = nameservers.iter().partition(|addr| addr.is_ipv4());
+    let strings = |addrs: Vec<&IpAddr>| addrs.iter().map(|addr| addr.to_string()).collect();
+    (strings(v4), strings(v6))
+}
+
+fn activate(system: &System, path: &str, body: &str) -> Result<(), Error> {
+    let content = system.read(path)?.unwrap_or_default();
+    system.write(path, &insert_global(&content, body))
+}
+
+/// Tells that the running machine only picks up the nameservers when its
+/// lease is renewed, e.g. with `command`.
+fn note_renewal(system: &System, command: &str) {
+    if system.is_live() {
+        system.note(Note::Info(format!(
+            "The nameservers take effect on the next lease renewal, e.g. after `{}`",
+            command
+        )));
+    }
+}
+
+fn deactivate(system: &System, path: &str) -> Result<(), Error> {
+    if let Some(content) = system.read(path)? {
+        let deactivated = block::remove(&content);
+        if deactivated != content {
+            system.write(path, &deactivated)?;
+        }
+    }
+    Ok(())
+}
+
+fn configured(system: &System, path: &str) -> Result<Vec<Configured>, Error> {
+    let body = system
+        .read(path)?
+        .and_then(|content| block::extract(&content));
+
+    let Some(body) = body else {
+        return Ok(Vec::new());
+    };
+    Ok(vec![Configured {
+        path: system.path(path)?,
+        nameservers: body
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .flat_map(|line| line.split([' ', ',', ';', '=']))
+            .filter_map(|word| word.parse().ok())
+            .collect(),
+    }])
+}
+
+/// Inserts the managed block before the first `interface`, `ssid` or
+/// `profile` section of dhcpcd.conf, whose options would otherwise only
+/// apply to that section. dhclient.conf delimits its sections with braces,
+/// so the block always lands outside of them.
+fn insert_global(content: &str, body: &str) -> String {
+    if block::extract(content).is_some() {
+        return block::insert(content, body);
+    }
+
+    let lines: Vec<_> = content.lines().collect();
+    let section = lines.iter().position(|line| {
+        let keyword = line.split_whitespace().next().unwrap_or_default();
+        matches!(keyword, "interface" | "ssid" | "profile")
+    });
+    match section {
+        Some(i) => {
+            let head: String = lines[..i]
+                .iter()
+                .map(|line| format!("{}\n", line))
+                .collect();
+            let tail: String = lines[i..]
+                .iter()
+                .map(|line| format!("{}\n", line))
+                .collect();
+            format!("{}{}", block::insert(&head, body), tail)
+        }
+        None => block::insert(content, body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::IpFamily;
+    use crate::testutil::{self, TempDir};
+
+    const DHCPCD_CONF: &str = "hostname
+option rapid_commit
+interface eth0
+static ip_address=192.168.1.10/24
+";
+
+    fn profile(family: IpFamily) -> Profile {
+        Profile {
+            family,
+            ..testutil::profile("adguard")
+        }
+    }
+
+    #[test]
+    fn dhclient_supersedes_both_families() -> Result<(), Error> {
+        let root = TempDir::new("dhclient-backend");
+        let system = System::with_root(root.path(), None);
+        let original = "request subnet-mask, routers, domain-name-servers;\n";
+        system.write(DHCLIENT_CONF_PATH, original)?;
+
+        Dhclient.activate(&system, &profile(IpFamily::Both))?;
+
+        let content = system.read(DHCLIENT_CONF_PATH)?.unwrap();
+        assert!(content.starts_with(original));
+        assert!(content.contains("supersede domain-name-servers 94.140.14.14, 94.140.15.15;\n"));
+        assert!(content.contains("supersede dhcp6.name-servers 2a10:50c0::ad1:ff;\n"));
+        assert_eq!(
+            Dhclient.configured(&system)?[0].nameservers,
+            ["94.140.14.14", "94.140.15.15", "2a10:50c0::ad1:ff"]
+                .map(|addr| addr.parse::<IpAddr>().unwrap())
+        );
+
+        Dhclient.deactivate(&system)?;
+
+        assert_eq!(system.read(DHCLIENT_CONF_PATH)?.unwrap(), original);
+        assert!(Dhclient.configured(&system)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn dhcpcd_block_goes_before_interface_sections() -> Result<(), Error> {
+        let root = TempDir::new("dhcpcd-backend");
+        let system = System::with_root(root.path(), None);
+        system.write(DHCPCD_CONF_PATH, DHCPCD_CONF)?;
+
+        Dhcpcd.activate(&system, &profile(IpFamily::V4))?;
+        Dhcpcd.activate(&system, &profile(IpFamily::V4))?;
+        assert!(system.take_notes()[0]
+            .to_string()
+            .contains("`dhcpcd --rebind`"));
+        let dry_run = System::with_root(root.path(), None).dry_run();
+        Dhcpcd.activate(&dry_run, &profile(IpFamily::V4))?;
+        assert!(dry_run.take_notes().is_empty());
+
+        let content = system.read(DHCPCD_CONF_PATH)?.unwrap();
+        assert_eq!(
+            content,
+            format!(
+                "hostname\noption rapid_commit\n{}\n# AdGuard DNS\n# {}\nstatic domain_name_servers=94.140.14.14 94.140.15.15\n{}\ninterface eth0\nstatic ip_address=192.168.1.10/24\n",
+                block::BEGIN_MARKER,
+                profile(IpFamily::V4).provider.url,
+                block::END_MARKER
+            )
+        );
+
+        Dhcpcd.deactivate(&system)?;
+
+        assert_eq!(system.read(DHCPCD_CONF_PATH)?.unwrap(), DHCPCD_CONF);
+        Ok(())
+    }
+}
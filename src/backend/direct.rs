@@ -0,0 +1,215 @@
+use std::path::PathBuf;
+
+use super::{Backend, Configured};
+use crate::block;
+use crate::error::Error;
+use crate::provider::Profile;
+use crate::resolv_conf::{Entry, ResolvConf, RESOLV_CONF_PATH};
+use crate::system::{Note, System};
+
+/// Writes the nameservers in a managed block of /etc/resolv.conf itself, for
+/// systems without resolvconf(8) such as containers and minimal images.
+///
+/// The original file, or the symlink it was, is saved in the state directory
+/// on the first activation and put back on deactivation.
+pub struct Direct;
+
+impl Backend for Direct {
+    fn name(&self) -> &'static str {
+        "resolv-conf"
+    }
+
+    fn activate(&self, system: &System, profile: &Profile) -> Result<(), Error> {
+        let link = system.read_link(RESOLV_CONF_PATH)?;
+        let current = system.read(RESOLV_CONF_PATH)?;
+
+        if !is_saved(system)? {
+            match (&link, &current) {
+                (Some(target), _) => {
+                    system.write(saved_symlink_path(system), &target.to_string_lossy())?
+                }
+                (None, Some(content)) => system.write(saved_path(system), content)?,
+                (None, None) => {}
+            }
+        }
+
+        let mut conf = ResolvConf::parse(current.as_deref().unwrap_or_default());
+        if link.is_some() {
+            // The file belongs to whatever the link points into, e.g. the
+            // systemd-resolved stub: only the lookup settings are worth keeping.
+            conf.retain(|entry| {
+                matches!(
+                    entry,
+                    Entry::Search(_) | Entry::Domain(_) | Entry::Sortlist(_) | Entry::Options(_)
+                )
+            });
+        } else {
+            conf.retain(|entry| !matches!(entry, Entry::Nameserver { .. }));
+        }
+
+        let content = block::insert(&conf.to_string(), &profile.config());
+        system.replace(RESOLV_CONF_PATH, &content)
+    }
+
+    fn deactivate(&self, system: &System) -> Result<(), Error> {
+        let current = match system.read_link(RESOLV_CONF_PATH)? {
+            Some(_) => None,
+            None => system.read(RESOLV_CONF_PATH)?,
+        };
+        let ours = current
+            .as_deref()
+            .is_some_and(|content| block::extract(content).is_some());
+
+        if let Some(target) = system.read(saved_symlink_path(system))? {
+            if ours {
+                system.symlink(target, RESOLV_CONF_PATH)?;
+            }
+        } else if let Some(original) = system.read(saved_path(system))? {
+            if ours {
+                system.replace(RESOLV_CONF_PATH, &original)?;
+            }
+        } else if let Some(content) = current.filter(|_| ours) {
+            system.replace(RESOLV_CONF_PATH, &block::remove(&content))?;
+        }
+
+        if is_saved(system)? && !ours {
+            system.note(Note::Info(format!(
+                "{} was replaced since activation, leaving it as is",
+                RESOLV_CONF_PATH
+            )));
+        }
+        system.remove(saved_symlink_path(system))?;
+        system.remove(saved_path(system))?;
+        Ok(())
+    }
+
+    fn configured(&self, system: &System) -> Result<Vec<Configured>, Error> {
+        if system.read_link(RESOLV_CONF_PATH)?.is_some() {
+            return Ok(Vec::new());
+        }
+        let body = system
+            .read(RESOLV_CONF_PATH)?
+            .and_then(|content| block::extract(&content));
+
+        let Some(body) = body else {
+            return Ok(Vec::new());
+        };
+        Ok(vec![Configured {
+            path: system.path(RESOLV_CONF_PATH)?,
+            nameservers: ResolvConf::parse(&body).nameservers().copied().collect(),
+        }])
+    }
+}
+
+fn saved_path(system: &System) -> PathBuf {
+    system.state_dir().join("resolv-conf/original")
+}
+
+fn saved_symlink_path(system: &System) -> PathBuf {
+    system.state_dir().join("resolv-conf/original.symlink")
+}
+
+fn is_saved(system: &System) -> Result<bool, Error> {
+    Ok(system.read(saved_path(system))?.is_some()
+        || system.read(saved_symlink_path(system))?.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, TempDir};
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+
+    const STUB: &str =
+        "# This is /run/systemd/resolve/stub-resolv.conf managed by man:systemd-resolved(8).
+nameserver 127.0.0.53
+options edns0 trust-ad
+search lan
+";
+
+    #[test]
+    fn regular_file_is_edited_and_restored() -> Result<(), Error> {
+        let root = TempDir::new("direct-file");
+        let system = System::with_root(root.path(), None);
+        let original = "# Written by hand\nnameserver 192.168.1.1\nsearch example.org\n";
+        system.write(RESOLV_CONF_PATH, original)?;
+
+        Direct.activate(&system, &testutil::profile("adguard"))?;
+        Direct.activate(&system, &testutil::profile("adguard"))?;
+
+        let content = system.read(RESOLV_CONF_PATH)?.unwrap();
+        assert!(content.starts_with("# Written by hand\nsearch example.org\n# BEGIN"));
+        assert!(!content.contains("192.168.1.1"));
+        assert_eq!(
+            Direct.configured(&system)?[0].nameservers,
+            testutil::profile("adguard").nameservers()
+        );
+
+        Direct.deactivate(&system)?;
+
+        assert_eq!(system.read(RESOLV_CONF_PATH)?.unwrap(), original);
+        assert!(Direct.configured(&system)?.is_empty());
+        assert!(!is_saved(&system)?);
+        Ok(())
+    }
+
+    #[test]
+    fn symlink_is_replaced_and_restored() -> Result<(), Error> {
+        let root = TempDir::new("direct-symlink");
+        let system = System::with_root(root.path(), None);
+        system.write("/run/systemd/resolve/stub-resolv.conf", STUB)?;
+        fs::create_dir_all(root.path().join("etc"))?;
+        symlink(
+            "../run/systemd/resolve/stub-resolv.conf",
+            root.path().join("etc/resolv.conf"),
+        )?;
+
+        Direct.activate(&system, &testutil::profile("adguard"))?;
+
+        assert_eq!(system.read_link(RESOLV_CONF_PATH)?, None);
+        let content = system.read(RESOLV_CONF_PATH)?.unwrap();
+        assert!(content.starts_with("options edns0 trust-ad\nsearch lan\n# BEGIN"));
+        assert!(!content.contains("127.0.0.53"));
+        assert_eq!(
+            system
+                .read("/run/systemd/resolve/stub-resolv.conf")?
+                .unwrap(),
+            STUB
+        );
+
+        Direct.deactivate(&system)?;
+
+        assert_eq!(
+            system.read_link(RESOLV_CONF_PATH)?.as_deref(),
+            Some(Path::new("../run/systemd/resolve/stub-resolv.conf"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn file_replaced_since_activation_is_left_alone() -> Result<(), Error> {
+        let root = TempDir::new("direct-replaced");
+        let system = System::with_root(root.path(), None);
+        system.write(RESOLV_CONF_PATH, "nameserver 192.168.1.1\n")?;
+
+        Direct.activate(&system, &testutil::profile("adguard"))?;
+        system.write(RESOLV_CONF_PATH, "nameserver 10.0.0.1\n")?;
+        Direct.deactivate(&system)?;
+
+        assert_eq!(
+            system.read(RESOLV_CONF_PATH)?.unwrap(),
+            "nameserver 10.0.0.1\n"
+        );
+        assert!(!is_saved(&system)?);
+        assert_eq!(
+            system.take_notes(),
+            [Note::Info(format!(
+                "{} was replaced since activation, leaving it as is",
+                RESOLV_CONF_PATH
+            ))]
+        );
+        Ok(())
+    }
+}
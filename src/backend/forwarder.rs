@@ -0,0 +1,285 @@
+use super::{Backend, Configured};
+use crate::error::Error;
+use crate::provider::Profile;
+use crate::system::System;
+
+const DNSMASQ_CONF_PATH: &str = "/etc/dnsmasq.d/cfg-adguard-dns.conf";
+const UNBOUND_CONF_PATH: &str = "/etc/unbound/unbound.conf.d/cfg-adguard-dns.conf";
+/// The certificates unbound authenticates DNS-over-TLS servers with.
+const CA_BUNDLE_PATH: &str = "/etc/ssl/certs/ca-certificates.crt";
+
+/// Makes a local dnsmasq(8) forward to the nameservers instead of the ones
+/// of /etc/resolv.conf.
+pub struct Dnsmasq;
+
+/// Makes a local unbound(8) forward every zone to the nameservers instead of
+/// resolving from the root servers.
+pub struct Unbound;
+
+impl Backend for Dnsmasq {
+    fn name(&self) -> &'static str {
+        "dnsmasq"
+    }
+
+    fn activate(&self, system: &System, profile: &Profile) -> Result<(), Error> {
+        let mut content = header(profile);
+        content.push_str("no-resolv\n");
+        for addr in profile.nameservers() {
+            content.push_str(&format!("server={}\n", addr));
+        }
+
+        let conf_file = format!("--conf-file={}", system.path(DNSMASQ_CONF_PATH)?.display());
+        install(
+            system,
+            DNSMASQ_CONF_PATH,
+            &content,
+            ("dnsmasq", &["--test", &conf_file]),
+            "dnsmasq",
+        )
+    }
+
+    fn deactivate(&self, system: &System) -> Result<(), Error> {
+        uninstall(system, DNSMASQ_CONF_PATH, "dnsmasq")
+    }
+
+    fn configured(&self, system: &System) -> Result<Vec<Configured>, Error> {
+        configured(system, DNSMASQ_CONF_PATH, "server=")
+    }
+}
+
+impl Backend for Unbound {
+    fn name(&self) -> &'static str {
+        "unbound"
+    }
+
+    fn supports_encryption(&self) -> bool {
+        true
+    }
+
+    fn activate(&self, system: &System, profile: &Profile) -> Result<(), Error> {
+        let mut content = header(profile);
+        if profile.encrypted {
+            content.push_str(&format!(
+                "server:\n    tls-cert-bundle: {}\n",
+                CA_BUNDLE_PATH
+            ));
+        }
+        content.push_str("forward-zone:\n    name: \".\"\n");
+        if profile.encrypted {
+            content.push_str("    forward-tls-upstream: yes\n");
+        }
+        for addr in profile.nameservers() {
+            match profile.encrypted {
+                true => content.push_str(&format!(
+                    "    forward-addr: {}@853#{}\n",
+                    addr,
+                    profile.tls_host()
+                )),
+                false => content.push_str(&format!("    forward-addr: {}\n", addr)),
+            }
+        }
+
+        install(
+            system,
+            UNBOUND_CONF_PATH,
+            &content,
+            ("unbound-checkconf", &[]),
+            "unbound",
+        )
+    }
+
+    fn deactivate(&self, system: &System) -> Result<(), Error> {
+        uninstall(system, UNBOUND_CONF_PATH, "unbound")
+    }
+
+    fn configured(&self, system: &System) -> Result<Vec<Configured>, Error> {
+        configured(system, UNBOUND_CONF_PATH, "forward-addr:")
+    }
+}
+
+fn header(profile: &Profile) -> String {
+    format!(
+        "# Managed by cfg-adguard-dns, removed by `cfg-adguard-dns deactivate`\n# {}\n# {}\n",
+        profile.provider.label, profile.provider.url
+    )
+}
+
+/// Writes a configuration file, checks it with the daemon's own checker and
+/// reloads the daemon; the transaction puts back a rejected file. The
+/// checker is skipped for a system below another root: the one installed
+/// here may not be the one of the image, and would check the files of this
+/// machine.
+fn install(
+    system: &System,
+    path: &str,
+    content: &str,
+    (checker, args): (&str, &[&str]),
+    service: &str,
+) -> Result<(), Error> {
+    system.write(path, content)?;
+    if system.is_running() {
+        system.run(checker, args)?;
+    }
+    reload(system, service)
+}
+
+fn uninstall(system: &System, path: &str, service: &str) -> Result<(), Error> {
+    if system.remove(path)? {
+        reload(system, service)?;
+    }
+    Ok(())
+}
+
+fn configured(system: &System, path: &str, prefix: &str) -> Result<Vec<Configured>, Error> {
+    let content = match system.read(path)? {
+        Some(content) => content,
+        None => return Ok(Vec::new()),
+    };
+
+    let nameservers = content
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix(prefix))
+        .filter_map(|server| server.trim().split(['@', '#']).next()?.parse().ok())
+        .collect();
+
+    Ok(vec![Configured {
+        path: system.path(path)?,
+        nameservers,
+    }])
+}
+
+fn reload(system: &System, service: &str) -> Result<(), Error> {
+    system.reload("systemctl", &["reload-or-restart", service])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::IpFamily;
+    use crate::testutil::{self, TempDir};
+
+    fn profile() -> Profile {
+        Profile {
+            family: IpFamily::Both,
+            ..testutil::profile("quad9")
+        }
+    }
+
+    #[test]
+    fn dnsmasq_forwards_to_the_nameservers() -> Result<(), Error> {
+        let root = TempDir::new("dnsmasq-backend");
+        testutil::fake_command(root.path(), "dnsmasq", "");
+        let bin_dir = testutil::fake_command(root.path(), "systemctl", "");
+        let system = System::with_root(root.path(), Some(&bin_dir));
+
+        Dnsmasq.activate(&system, &profile())?;
+
+        let content = system.read(DNSMASQ_CONF_PATH)?.unwrap();
+        assert!(content
+            .ends_with("no-resolv\nserver=9.9.9.9\nserver=2620:fe::fe\nserver=149.112.112.112\n"));
+        assert_eq!(
+            Dnsmasq.configured(&system)?[0].nameservers,
+            profile().nameservers()
+        );
+        assert_eq!(
+            testutil::command_log(root.path(), "dnsmasq"),
+            format!(
+                "--test --conf-file={}\n",
+                system.path(DNSMASQ_CONF_PATH)?.display()
+            )
+        );
+
+        Dnsmasq.deactivate(&system)?;
+        Dnsmasq.deactivate(&system)?;
+
+        assert!(Dnsmasq.configured(&system)?.is_empty());
+        assert_eq!(
+            testutil::command_log(root.path(), "systemctl"),
+            "reload-or-restart dnsmasq\nreload-or-restart dnsmasq\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unbound_forwards_the_root_zone() -> Result<(), Error> {
+        let root = TempDir::new("unbound-backend");
+        testutil::fake_command(root.path(), "unbound-checkconf", "");
+        let bin_dir = testutil::fake_command(root.path(), "systemctl", "");
+        let system = System::with_root(root.path(), Some(&bin_dir));
+
+        Unbound.activate(&system, &profile())?;
+
+        let content = system.read(UNBOUND_CONF_PATH)?.unwrap();
+        assert!(content.contains("forward-zone:\n    name: \".\"\n    forward-addr: 9.9.9.9\n"));
+        assert_eq!(
+            Unbound.configured(&system)?[0].nameservers,
+            profile().nameservers()
+        );
+        assert_eq!(
+            testutil::command_log(root.path(), "systemctl"),
+            "reload-or-restart unbound\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unbound_forwards_over_tls() -> Result<(), Error> {
+        let root = TempDir::new("unbound-encrypted");
+        testutil::fake_command(root.path(), "unbound-checkconf", "");
+        let bin_dir = testutil::fake_command(root.path(), "systemctl", "");
+        let system = System::with_root(root.path(), Some(&bin_dir));
+        let profile = Profile {
+            encrypted: true,
+            ..profile()
+        };
+
+        Unbound.activate(&system, &profile)?;
+
+        let content = system.read(UNBOUND_CONF_PATH)?.unwrap();
+        assert!(content.contains("server:\n    tls-cert-bundle: "));
+        assert!(content.contains(
+            "    forward-tls-upstream: yes\n    forward-addr: 9.9.9.9@853#dns.quad9.net\n"
+        ));
+        assert_eq!(
+            Unbound.configured(&system)?[0].nameservers,
+            profile.nameservers()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn configuration_below_another_root_is_not_checked() -> Result<(), Error> {
+        let root = TempDir::new("unbound-alternate-root");
+        let system = System::at_root(root.path());
+
+        Unbound.activate(&system, &profile())?;
+
+        assert_eq!(
+            Unbound.configured(&system)?[0].nameservers,
+            profile().nameservers()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejected_configuration_is_removed() -> Result<(), Error> {
+        let root = TempDir::new("unbound-rejected");
+        testutil::fake_command(
+            root.path(),
+            "unbound-checkconf",
+            "echo 'syntax error' >&2; exit 1",
+        );
+        let bin_dir = testutil::fake_command(root.path(), "systemctl", "");
+        let system = System::with_root(root.path(), Some(&bin_dir));
+
+        let err = system
+            .transaction(|| Unbound.activate(&system, &profile()))
+            .unwrap_err();
+
+        assert!(err.to_string().contains("syntax error"));
+        assert_eq!(system.read(UNBOUND_CONF_PATH)?, None);
+        assert_eq!(testutil::command_log(root.path(), "systemctl"), "");
+        Ok(())
+    }
+}
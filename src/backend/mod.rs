@@ -0,0 +1,142 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use crate::error::Error;
+use crate::provider::Profile;
+use crate::system::System;
+
+pub mod detect;
+pub mod dhcp;
+pub mod direct;
+pub mod forwarder;
+pub mod netplan;
+pub mod networkmanager;
+pub mod resolvconf;
+pub mod resolved;
+
+/// A resolver stack the nameservers can be configured in.
+pub trait Backend: Sync {
+    /// The name given to `--backend`.
+    fn name(&self) -> &'static str;
+
+    /// Whether `activate` can configure DNS-over-TLS.
+    fn supports_encryption(&self) -> bool {
+        false
+    }
+
+    /// Points the resolver at the profile's nameservers.
+    fn activate(&self, system: &System, profile: &Profile) -> Result<(), Error>;
+
+    /// Removes whatever `activate` configured. Does nothing when the backend
+    /// is not configured.
+    fn deactivate(&self, system: &System) -> Result<(), Error>;
+
+    /// The nameservers currently configured through this backend, one entry
+    /// per file, empty when the backend is not configured.
+    fn configured(&self, system: &System) -> Result<Vec<Configured>, Error>;
+}
+
+/// Nameservers written by a backend in one of its files.
+#[derive(Debug, PartialEq)]
+pub struct Configured {
+    pub path: PathBuf,
+    pub nameservers: Vec<IpAddr>,
+}
+
+pub const BACKENDS: &[&dyn Backend] = &[
+    &resolvconf::Resolvconf,
+    &resolved::Resolved,
+    &networkmanager::NetworkManager,
+    &netplan::Netplan,
+    &dhcp::Dhclient,
+    &dhcp::Dhcpcd,
+    &forwarder::Dnsmasq,
+    &forwarder::Unbound,
+    &direct::Direct,
+];
+
+pub fn find(name: &str) -> Option<&'static dyn Backend> {
+    BACKENDS
+        .iter()
+        .copied()
+        .find(|backend| backend.name() == name)
+}
+
+/// Refuses encrypted profiles the backend cannot configure, rather than
+/// silently falling back to plain DNS, and plain DNS to providers which do
+/// not answer it.
+pub fn check_supported(backend: &dyn Backend, profile: &Profile) -> Result<(), Error> {
+    if profile.provider.encrypted_only && !profile.encrypted && !profile.via_proxy {
+        return Err(Error::Unsupported(format!(
+            "{} only answers encrypted DNS, add `--encrypted` or `--via-proxy`",
+            profile.provider.label
+        )));
+    }
+    if !profile.encrypted || backend.supports_encryption() {
+        return Ok(());
+    }
+
+    let supported: Vec<_> = BACKENDS
+        .iter()
+        .filter(|backend| backend.supports_encryption())
+        .map(|backend| format!("`--backend {}`", backend.name()))
+        .collect();
+    Err(Error::Unsupported(format!(
+        "the {} backend cannot configure encrypted DNS, use {}",
+        backend.name(),
+        supported.join(" or ")
+    )))
+}
+
+impl fmt::Debug for dyn Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl PartialEq for dyn Backend {
+    fn eq(&self, other: &dyn Backend) -> bool {
+        self.name() == other.name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn encryption_is_refused_by_plain_backends() {
+        let mut profile = testutil::profile("adguard");
+        assert!(check_supported(&resolvconf::Resolvconf, &profile).is_ok());
+
+        profile.encrypted = true;
+        let err = check_supported(&resolvconf::Resolvconf, &profile).unwrap_err();
+        assert!(matches!(err, Error::Unsupported(_)));
+        assert!(err.to_string().contains("`--backend systemd-resolved`"));
+        assert!(check_supported(&resolved::Resolved, &profile).is_ok());
+    }
+
+    #[test]
+    fn plain_dns_is_refused_for_encrypted_only_providers() {
+        let mut profile = testutil::profile("mullvad");
+        let err = check_supported(&resolved::Resolved, &profile).unwrap_err();
+        assert!(matches!(err, Error::Unsupported(_)));
+
+        profile.encrypted = true;
+        assert!(check_supported(&resolved::Resolved, &profile).is_ok());
+        profile.encrypted = false;
+        profile.via_proxy = true;
+        assert!(check_supported(&resolvconf::Resolvconf, &profile).is_ok());
+    }
+
+    #[test]
+    fn backend_names_are_unique() {
+        for (i, backend) in BACKENDS.iter().enumerate() {
+            assert!(BACKENDS[i + 1..]
+                .iter()
+                .all(|other| other.name() != backend.name()))
+        }
+    }
+}
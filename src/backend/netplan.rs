@@ -0,0 +1,336 @@
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use super::{Backend, Configured};
+use crate::error::Error;
+use crate::provider::Profile;
+use crate::system::System;
+
+pub const NETPLAN_DIR: &str = "/etc/netplan";
+const OVERLAY_PATH: &str = "/etc/netplan/99-cfg-adguard-dns.yaml";
+
+/// Sections of the netplan configuration whose interfaces get the overlay.
+const SECTIONS: &[&str] = &["ethernets", "wifis"];
+
+/// Writes a netplan overlay giving the interfaces the nameservers and
+/// ignoring the ones learned through DHCP, so that they survive
+/// `netplan apply` and reboots. netplan merges it over the other files of
+/// /etc/netplan, the highest name winning.
+pub struct Netplan;
+
+impl Backend for Netplan {
+    fn name(&self) -> &'static str {
+        "netplan"
+    }
+
+    fn activate(&self, system: &System, profile: &Profile) -> Result<(), Error> {
+        let declared = declared_interfaces(system)?;
+        let interfaces: Vec<(String, String)> = if profile.interfaces.is_empty() {
+            declared
+        } else {
+            profile
+                .interfaces
+                .iter()
+                .map(|name| {
+                    let section = declared
+                        .iter()
+                        .find(|(_, declared)| declared == name)
+                        .map_or("ethernets", |(section, _)| section.as_str());
+                    (section.to_string(), name.clone())
+                })
+                .collect()
+        };
+        if interfaces.is_empty() {
+            return Err(Error::Io(io::Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "no interface is configured in {}, use `--interface <name>`",
+                    NETPLAN_DIR
+                ),
+            )));
+        }
+
+        // netplan warns about configurations readable by everyone.
+        system.write_with_mode(OVERLAY_PATH, &overlay(profile, &interfaces), 0o600)?;
+        system.reload("netplan", &["generate"])?;
+        system.reload("netplan", &["apply"])?;
+        Ok(())
+    }
+
+    fn deactivate(&self, system: &System) -> Result<(), Error> {
+        if system.remove(OVERLAY_PATH)? {
+            system.reload("netplan", &["generate"])?;
+            system.reload("netplan", &["apply"])?;
+        }
+        Ok(())
+    }
+
+    fn configured(&self, system: &System) -> Result<Vec<Configured>, Error> {
+        let content = match system.read(OVERLAY_PATH)? {
+            Some(content) => content,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut nameservers = Vec::new();
+        for list in content
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("addresses:"))
+        {
+            for addr in list.trim().trim_matches(['[', ']']).split(',') {
+                if let Ok(addr) = addr.trim().parse() {
+                    if !nameservers.contains(&addr) {
+                        nameservers.push(addr);
+                    }
+                }
+            }
+        }
+
+        Ok(vec![Configured {
+            path: system.path(OVERLAY_PATH)?,
+            nameservers,
+        }])
+    }
+}
+
+/// The netplan configuration files other than the overlay.
+pub fn config_files(system: &System) -> Result<Vec<PathBuf>, Error> {
+    Ok(system
+        .read_dir(NETPLAN_DIR)?
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "yaml"))
+        .filter(|path| path != Path::new(OVERLAY_PATH))
+        .collect())
+}
+
+/// The interfaces declared in the netplan configuration, as
+/// `(section, name)`.
+fn declared_interfaces(system: &System) -> Result<Vec<(String, String)>, Error> {
+    let mut interfaces = Vec::new();
+    for path in config_files(system)? {
+        for interface in mapping_keys(&system.read(&path)?.unwrap_or_default()) {
+            if !interfaces.contains(&interface) {
+                interfaces.push(interface);
+            }
+        }
+    }
+    Ok(interfaces)
+}
+
+/// Finds the keys of the `network.<section>` mappings in a netplan YAML
+/// file. Only the block style netplan documents is understood.
+fn mapping_keys(content: &str) -> Vec<(String, String)> {
+    let mut keys = Vec::new();
+    let mut path: Vec<(usize, String)> = Vec::new();
+
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        while path.last().is_some_and(|(parent, _)| *parent >= indent) {
+            path.pop();
+        }
+        if trimmed.starts_with('-') {
+            continue;
+        }
+        let Some((key, _)) = trimmed.split_once(':') else {
+            continue;
+        };
+        let key = key.trim().trim_matches(['"', '\'']).to_string();
+
+        if let [(_, network), (_, section)] = path.as_slice() {
+            if network == "network" && SECTIONS.contains(&section.as_str()) {
+                keys.push((section.clone(), key.clone()));
+            }
+        }
+        path.push((indent, key));
+    }
+
+    keys
+}
+
+fn overlay(profile: &Profile, interfaces: &[(String, String)]) -> String {
+    let addresses: Vec<_> = profile
+        .nameservers()
+        .iter()
+        .map(|addr| addr.to_string())
+        .collect();
+
+    let mut content = format!(
+        "# Managed by cfg-adguard-dns, removed by `cfg-adguard-dns deactivate`
+# {}
+# {}
+network:
+  version: 2
+",
+        profile.provider.label, profile.provider.url
+    );
+    for section in SECTIONS {
+        let names: Vec<_> = interfaces
+            .iter()
+            .filter(|(interface_section, _)| interface_section == section)
+            .map(|(_, name)| name)
+            .collect();
+        if names.is_empty() {
+            continue;
+        }
+
+        content.push_str(&format!("  {}:\n", section));
+        for name in names {
+            // networkd wants the same use-dns in both overrides.
+            content.push_str(&format!(
+                "    {}:
+      nameservers:
+        addresses: [{}]
+      dhcp4-overrides:
+        use-dns: false
+      dhcp6-overrides:
+        use-dns: false
+",
+                name,
+                addresses.join(", ")
+            ));
+        }
+    }
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::IpFamily;
+    use crate::testutil::{self, TempDir};
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    const CLOUD_INIT: &str = "# This file is generated from information provided by the datasource.
+network:
+    ethernets:
+        eth0:
+            dhcp4: true
+            match:
+                macaddress: 52:54:00:12:34:56
+            set-name: eth0
+    version: 2
+    wifis:
+        \"wlan0\":
+            access-points:
+                home: {password: secret}
+            dhcp4: true
+";
+
+    fn profile(interfaces: &[&str]) -> Profile {
+        Profile {
+            family: IpFamily::Both,
+            interfaces: interfaces.iter().map(|name| name.to_string()).collect(),
+            ..testutil::profile("adguard")
+        }
+    }
+
+    #[test]
+    fn interfaces_are_found_in_ethernets_and_wifis() {
+        assert_eq!(
+            mapping_keys(CLOUD_INIT),
+            [
+                (String::from("ethernets"), String::from("eth0")),
+                (String::from("wifis"), String::from("wlan0")),
+            ]
+        );
+    }
+
+    #[test]
+    fn activate_writes_overlay_and_deactivate_removes_it() -> Result<(), Error> {
+        let root = TempDir::new("netplan-backend");
+        let bin_dir = testutil::fake_command(root.path(), "netplan", "");
+        let system = System::with_root(root.path(), Some(&bin_dir));
+        system.write("/etc/netplan/50-cloud-init.yaml", CLOUD_INIT)?;
+
+        Netplan.activate(&system, &profile(&[]))?;
+
+        let content = system.read(OVERLAY_PATH)?.unwrap();
+        assert!(content.contains(
+            "  ethernets:
+    eth0:
+      nameservers:
+        addresses: [94.140.14.14, 2a10:50c0::ad1:ff, 94.140.15.15]
+      dhcp4-overrides:
+        use-dns: false
+"
+        ));
+        assert!(content.contains("  wifis:\n    wlan0:\n"));
+        let mode = fs::metadata(system.path(OVERLAY_PATH)?)?
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(
+            Netplan.configured(&system)?[0].nameservers,
+            profile(&[]).nameservers()
+        );
+
+        Netplan.deactivate(&system)?;
+        Netplan.deactivate(&system)?;
+
+        assert!(Netplan.configured(&system)?.is_empty());
+        assert_eq!(
+            testutil::command_log(root.path(), "netplan"),
+            "generate\napply\ngenerate\napply\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn selected_interfaces_only() -> Result<(), Error> {
+        let root = TempDir::new("netplan-selected");
+        let bin_dir = testutil::fake_command(root.path(), "netplan", "");
+        let system = System::with_root(root.path(), Some(&bin_dir));
+        system.write("/etc/netplan/50-cloud-init.yaml", CLOUD_INIT)?;
+
+        Netplan.activate(&system, &profile(&["wlan0"]))?;
+
+        let content = system.read(OVERLAY_PATH)?.unwrap();
+        assert!(content.contains("  wifis:\n    wlan0:\n"));
+        assert!(!content.contains("eth0"));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_overlay_is_removed() -> Result<(), Error> {
+        let root = TempDir::new("netplan-invalid");
+        let bin_dir = testutil::fake_command(root.path(), "netplan", "exit 1");
+        let system = System::with_root(root.path(), Some(&bin_dir));
+
+        assert!(matches!(
+            Netplan.activate(&system, &profile(&[])).unwrap_err(),
+            Error::Io(err) if err.kind() == ErrorKind::NotFound
+        ));
+        assert!(system
+            .transaction(|| Netplan.activate(&system, &profile(&["eth0"])))
+            .is_err());
+        assert_eq!(system.read(OVERLAY_PATH)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn working_overlay_is_kept_when_the_new_one_is_invalid() -> Result<(), Error> {
+        let root = TempDir::new("netplan-keep");
+        let bin_dir = testutil::fake_command(root.path(), "netplan", "");
+        let system = System::with_root(root.path(), Some(&bin_dir));
+        system.transaction(|| Netplan.activate(&system, &profile(&["eth0"])))?;
+        let working = system.read(OVERLAY_PATH)?.unwrap();
+
+        // netplan rejects the new overlay only.
+        let check = format!("! grep -q wlan0 '{}'", system.path(OVERLAY_PATH)?.display());
+        testutil::fake_command(root.path(), "netplan", &check);
+        let result = system.transaction(|| Netplan.activate(&system, &profile(&["wlan0"])));
+
+        assert!(matches!(result, Err(Error::CommandFailed { .. })));
+
+        assert_eq!(system.read(OVERLAY_PATH)?.unwrap(), working);
+        let mode = fs::metadata(system.path(OVERLAY_PATH)?)?
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        Ok(())
+    }
+}
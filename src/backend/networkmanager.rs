@@ -0,0 +1,437 @@
+use std::io::{self, ErrorKind};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use super::{Backend, Configured};
+use crate::error::Error;
+use crate::provider::Profile;
+use crate::system::System;
+
+const CONNECTIONS_DIR: &str = "/etc/NetworkManager/system-connections";
+
+/// Keyfile properties changed on activation, as `(section, key)`.
+const MANAGED_KEYS: &[(&str, &str)] = &[
+    ("ipv4", "dns"),
+    ("ipv4", "ignore-auto-dns"),
+    ("ipv6", "dns"),
+    ("ipv6", "ignore-auto-dns"),
+];
+
+/// Sets the nameservers on the active NetworkManager connections by editing
+/// their keyfiles, so that they survive DHCP leases. The previous values of
+/// the edited properties are saved in the state directory and put back on
+/// deactivation.
+pub struct NetworkManager;
+
+impl Backend for NetworkManager {
+    fn name(&self) -> &'static str {
+        "networkmanager"
+    }
+
+    fn activate(&self, system: &System, profile: &Profile) -> Result<(), Error> {
+        let uuids = active_connections(system)?;
+        if uuids.is_empty() {
+            return Err(Error::Io(io::Error::new(
+                ErrorKind::NotFound,
+                "no active NetworkManager connection",
+            )));
+        }
+
+        let nameservers = profile.nameservers();
+        for uuid in &uuids {
+            let path = find_keyfile(system, uuid)?;
+            let mut content = system.read(&path)?.unwrap_or_default();
+
+            let saved = saved_path(system, uuid);
+            let values = match system.read(&saved)? {
+                Some(values) => values,
+                None => {
+                    let values = save(&content);
+                    system.write(&saved, &values)?;
+                    values
+                }
+            };
+
+            for (section, is_family) in [
+                ("ipv4", IpAddr::is_ipv4 as fn(&IpAddr) -> bool),
+                ("ipv6", IpAddr::is_ipv6),
+            ] {
+                let addrs: Vec<_> = nameservers.iter().filter(|addr| is_family(addr)).collect();
+                let method = get(&content, section, "method");
+                if addrs.is_empty() || matches!(method.as_deref(), Some("disabled" | "ignore")) {
+                    // A previous activation may have set them, e.g. for both families.
+                    content = restore_section(&content, &values, section);
+                    continue;
+                }
+
+                let dns: String = addrs.iter().map(|addr| format!("{};", addr)).collect();
+                content = set(&content, section, "dns", Some(&dns));
+                content = set(&content, section, "ignore-auto-dns", Some("true"));
+            }
+            system.write(&path, &content)?;
+        }
+
+        reload(system, &uuids)
+    }
+
+    fn deactivate(&self, system: &System) -> Result<(), Error> {
+        let mut uuids = Vec::new();
+
+        for saved in system.read_dir(state_dir(system))? {
+            let uuid = file_name(&saved);
+            let values = system.read(&saved)?.unwrap_or_default();
+
+            if let Ok(path) = find_keyfile(system, &uuid) {
+                let mut content = system.read(&path)?.unwrap_or_default();
+                for section in ["ipv4", "ipv6"] {
+                    content = restore_section(&content, &values, section);
+                }
+                system.write(&path, &content)?;
+                uuids.push(uuid);
+            }
+            system.remove(&saved)?;
+        }
+
+        if uuids.is_empty() {
+            return Ok(());
+        }
+        reload(system, &uuids)
+    }
+
+    fn configured(&self, system: &System) -> Result<Vec<Configured>, Error> {
+        let mut configured = Vec::new();
+
+        for saved in system.read_dir(state_dir(system))? {
+            if let Ok(path) = find_keyfile(system, &file_name(&saved)) {
+                let content = system.read(&path)?.unwrap_or_default();
+                let nameservers = ["ipv4", "ipv6"]
+                    .iter()
+                    .filter_map(|section| get(&content, section, "dns"))
+                    .flat_map(|dns| {
+                        dns.split([';', ','])
+                            .filter_map(|addr| addr.trim().parse().ok())
+                            .collect::<Vec<_>>()
+                    })
+                    .collect();
+                configured.push(Configured {
+                    path: system.path(&path)?,
+                    nameservers,
+                });
+            }
+        }
+
+        Ok(configured)
+    }
+}
+
+/// The UUIDs of the active connections, loopback excluded.
+fn active_connections(system: &System) -> Result<Vec<String>, Error> {
+    let output = system.query(
+        "nmcli",
+        &["-t", "-f", "UUID,TYPE", "connection", "show", "--active"],
+    )?;
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(_, kind)| *kind != "loopback")
+        .map(|(uuid, _)| uuid.to_string())
+        .collect())
+}
+
+fn find_keyfile(system: &System, uuid: &str) -> Result<PathBuf, Error> {
+    for path in system.read_dir(CONNECTIONS_DIR)? {
+        if let Some(content) = system.read(&path)? {
+            if get(&content, "connection", "uuid").as_deref() == Some(uuid) {
+                return Ok(path);
+            }
+        }
+    }
+
+    Err(Error::Io(io::Error::new(
+        ErrorKind::NotFound,
+        format!(
+            "no keyfile for connection {} in {}, is it stored by another settings plugin?",
+            uuid, CONNECTIONS_DIR
+        ),
+    )))
+}
+
+fn reload(system: &System, uuids: &[String]) -> Result<(), Error> {
+    system.reload("nmcli", &["connection", "reload"])?;
+    for uuid in uuids {
+        system.reload("nmcli", &["connection", "up", "uuid", uuid])?;
+    }
+    Ok(())
+}
+
+fn state_dir(system: &System) -> PathBuf {
+    system.state_dir().join("networkmanager")
+}
+
+fn saved_path(system: &System, uuid: &str) -> PathBuf {
+    state_dir(system).join(uuid)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Renders the current value of every managed property as a keyfile of its
+/// own, absent properties being left out.
+fn save(content: &str) -> String {
+    let mut saved = String::from("[saved]\n");
+    for (section, key) in MANAGED_KEYS {
+        if let Some(value) = get(content, section, key) {
+            saved.push_str(&format!("{}.{}={}\n", section, key, value));
+        }
+    }
+    saved
+}
+
+/// Puts back the managed properties of `[section]` from their `saved` values.
+fn restore_section(content: &str, saved: &str, section: &str) -> String {
+    let mut content = content.to_string();
+    for (_, key) in MANAGED_KEYS.iter().filter(|(name, _)| *name == section) {
+        let value = get(saved, "saved", &format!("{}.{}", section, key));
+        content = set(&content, section, key, value.as_deref());
+    }
+    content
+}
+
+/// Reads `key` of `[section]` in a keyfile.
+fn get(content: &str, section: &str, key: &str) -> Option<String> {
+    let mut current = "";
+    for line in content.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = name;
+        } else if current == section {
+            if let Some((k, value)) = line.split_once('=') {
+                if k.trim() == key {
+                    return Some(value.trim().to_string());
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Sets `key` of `[section]` in a keyfile, or removes it when `value` is
+/// `None`. Every other line is kept as is.
+fn set(content: &str, section: &str, key: &str, value: Option<&str>) -> String {
+    let mut lines: Vec<String> = content.lines().map(String::from).collect();
+    let mut current = String::new();
+    let mut section_end = None;
+    let mut found = None;
+
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        if let Some(name) = trimmed.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = name.to_string();
+        } else if current == section {
+            if trimmed.split_once('=').map(|(k, _)| k.trim()) == Some(key) {
+                found = Some(i);
+            }
+            if !trimmed.is_empty() {
+                section_end = Some(i + 1);
+            }
+        }
+        if current == section && section_end.is_none() {
+            section_end = Some(i + 1);
+        }
+    }
+
+    let line = value.map(|value| format!("{}={}", key, value));
+    match (found, line) {
+        (Some(i), Some(line)) => lines[i] = line,
+        (Some(i), None) => {
+            lines.remove(i);
+        }
+        (None, Some(line)) => match section_end {
+            Some(end) => lines.insert(end, line),
+            None => {
+                if lines.last().is_some_and(|last| !last.trim().is_empty()) {
+                    lines.push(String::new());
+                }
+                lines.push(format!("[{}]", section));
+                lines.push(line);
+            }
+        },
+        (None, None) => {}
+    }
+
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::IpFamily;
+    use crate::testutil::{self, TempDir};
+    use std::fs;
+
+    const WIFI_UUID: &str = "6c3f1e2a-4f57-4a43-9e1b-2f3c1f1b0a01";
+
+    fn fixture_root(name: &str) -> TempDir {
+        let root = TempDir::new(name);
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/networkmanager");
+        let dir = root.path().join("etc/NetworkManager/system-connections");
+        fs::create_dir_all(&dir).unwrap();
+        for entry in fs::read_dir(fixtures).unwrap() {
+            let entry = entry.unwrap();
+            fs::copy(entry.path(), dir.join(entry.file_name())).unwrap();
+        }
+        root
+    }
+
+    fn fake_nmcli(root: &Path) -> PathBuf {
+        testutil::fake_command(
+            root,
+            "nmcli",
+            &format!(
+                "[ \"$1\" = -t ] && printf '{}:802-11-wireless\\nlo:loopback\\n'; exit 0",
+                WIFI_UUID
+            ),
+        )
+    }
+
+    #[test]
+    fn set_and_get_keep_other_lines() {
+        let content = "[connection]\nid=Home\n\n[ipv4]\nmethod=auto\n\n[proxy]\n";
+
+        let content = set(content, "ipv4", "dns", Some("9.9.9.9;"));
+        let content = set(&content, "ipv6", "dns", Some("2620:fe::fe;"));
+
+        assert_eq!(
+            content,
+            "[connection]\nid=Home\n\n[ipv4]\nmethod=auto\ndns=9.9.9.9;\n\n[proxy]\n\n[ipv6]\ndns=2620:fe::fe;\n"
+        );
+        assert_eq!(get(&content, "ipv4", "dns").as_deref(), Some("9.9.9.9;"));
+        assert_eq!(get(&content, "connection", "dns"), None);
+        assert_eq!(
+            set(&set(&content, "ipv4", "dns", None), "ipv6", "dns", None),
+            "[connection]\nid=Home\n\n[ipv4]\nmethod=auto\n\n[proxy]\n\n[ipv6]\n"
+        );
+    }
+
+    #[test]
+    fn activate_and_deactivate_restore_previous_values() -> Result<(), Error> {
+        let root = fixture_root("networkmanager-backend");
+        let bin_dir = fake_nmcli(root.path());
+        let system = System::with_root(root.path(), Some(&bin_dir));
+        let keyfile = root
+            .path()
+            .join("etc/NetworkManager/system-connections/Home.nmconnection");
+        let original = fs::read_to_string(&keyfile)?;
+        let profile = Profile {
+            family: IpFamily::Both,
+            ..testutil::profile("adguard")
+        };
+
+        NetworkManager.activate(&system, &profile)?;
+        NetworkManager.activate(&system, &profile)?;
+
+        let content = fs::read_to_string(&keyfile)?;
+        assert_eq!(
+            get(&content, "ipv4", "dns").as_deref(),
+            Some("94.140.14.14;94.140.15.15;")
+        );
+        assert_eq!(
+            get(&content, "ipv4", "ignore-auto-dns").as_deref(),
+            Some("true")
+        );
+        assert_eq!(
+            get(&content, "ipv6", "dns").as_deref(),
+            Some("2a10:50c0::ad1:ff;")
+        );
+        let configured = NetworkManager.configured(&system)?;
+        assert_eq!(configured.len(), 1);
+        assert_eq!(
+            configured[0].nameservers,
+            ["94.140.14.14", "94.140.15.15", "2a10:50c0::ad1:ff"]
+                .map(|addr| addr.parse::<IpAddr>().unwrap())
+        );
+
+        NetworkManager.deactivate(&system)?;
+
+        assert_eq!(fs::read_to_string(&keyfile)?, original);
+        assert!(NetworkManager.configured(&system)?.is_empty());
+        assert!(
+            testutil::command_log(root.path(), "nmcli").ends_with(&format!(
+                "connection reload\nconnection up uuid {}\n",
+                WIFI_UUID
+            ))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn family_no_longer_activated_is_restored() -> Result<(), Error> {
+        let root = fixture_root("networkmanager-families");
+        let system = System::with_root(root.path(), Some(&fake_nmcli(root.path())));
+        let keyfile = root
+            .path()
+            .join("etc/NetworkManager/system-connections/Home.nmconnection");
+        let original = fs::read_to_string(&keyfile)?;
+        let both = Profile {
+            family: IpFamily::Both,
+            ..testutil::profile("adguard")
+        };
+
+        NetworkManager.activate(&system, &both)?;
+        NetworkManager.activate(&system, &testutil::profile("quad9"))?;
+
+        let content = fs::read_to_string(&keyfile)?;
+        assert_eq!(
+            get(&content, "ipv4", "dns").as_deref(),
+            Some("9.9.9.9;149.112.112.112;")
+        );
+        assert_eq!(get(&content, "ipv6", "dns"), None);
+        assert_eq!(get(&content, "ipv6", "ignore-auto-dns"), None);
+
+        NetworkManager.deactivate(&system)?;
+
+        assert_eq!(fs::read_to_string(&keyfile)?, original);
+        Ok(())
+    }
+
+    #[test]
+    fn disabled_ip_families_are_left_alone() -> Result<(), Error> {
+        let root = fixture_root("networkmanager-disabled");
+        let system = System::with_root(root.path(), Some(&fake_nmcli(root.path())));
+        let keyfile = root
+            .path()
+            .join("etc/NetworkManager/system-connections/Home.nmconnection");
+        fs::write(
+            &keyfile,
+            set(
+                &fs::read_to_string(&keyfile)?,
+                "ipv6",
+                "method",
+                Some("disabled"),
+            ),
+        )?;
+        let profile = Profile {
+            family: IpFamily::Both,
+            ..testutil::profile("adguard")
+        };
+
+        NetworkManager.activate(&system, &profile)?;
+
+        assert_eq!(get(&fs::read_to_string(&keyfile)?, "ipv6", "dns"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_keyfile_is_reported() {
+        let root = TempDir::new("networkmanager-missing");
+        let system = System::with_root(root.path(), Some(&fake_nmcli(root.path())));
+        let profile = testutil::profile("adguard");
+
+        let err = NetworkManager.activate(&system, &profile).unwrap_err();
+        assert!(matches!(err, Error::Io(err) if err.kind() == ErrorKind::NotFound));
+    }
+}
@@ -0,0 +1,411 @@
+use std::env;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use super::{Backend, Configured};
+use crate::backup::{self, Backup};
+use crate::block;
+use crate::error::Error;
+use crate::provider::Profile;
+use crate::resolv_conf::{ResolvConf, RESOLV_CONF_PATH};
+use crate::system::{Note, System};
+
+const RESOLVCONF_HEAD_ENV_VAR: &str = "RESOLVCONF_HEAD_PATH";
+const RESOLVCONF_HEAD_DEFAULT_PATH: &str = "/etc/resolvconf/resolv.conf.d/head";
+
+const DEFAULT_TEMPLATE: &str = "
+# Dynamic resolv.conf(5) file for glibc resolver(3) generated by resolvconf(8)
+#     DO NOT EDIT THIS FILE BY HAND -- YOUR CHANGES WILL BE OVERWRITTEN
+# 127.0.0.53 is the systemd-resolved stub resolver.
+# run \"systemd-resolve --status\" to see details about the actual nameservers.
+";
+
+/// What releases prior to managed blocks appended to the head file, typo included.
+const LEGACY_ADGUARD_DNS_SERVER_CONFIG: &str = " 
+# AdGuard DNS 
+# https://adguard-dns.com/en/public-dns.html
+nameserver 94.140.14.14
+nameserver 94.149.15.15
+";
+
+/// Writes the nameservers in the managed block of the resolvconf head file,
+/// which resolvconf(8) puts at the top of the generated resolv.conf.
+pub struct Resolvconf;
+
+/// Reported when /etc/resolv.conf does not start with the activated
+/// nameservers after `resolvconf -u`, e.g. because it is not generated by
+/// resolvconf.
+#[derive(Debug, PartialEq)]
+pub struct NotApplied {
+    pub expected: Vec<IpAddr>,
+    pub found: Vec<IpAddr>,
+}
+
+impl fmt::Display for NotApplied {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list = |addrs: &[IpAddr]| match addrs {
+            [] => String::from("no nameserver"),
+            _ => addrs
+                .iter()
+                .map(IpAddr::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        write!(
+            f,
+            "{} lists {} instead of {} after `resolvconf -u`, the resolver does not read the head file; try `cfg-adguard-dns backend`",
+            RESOLV_CONF_PATH,
+            list(&self.found),
+            list(&self.expected)
+        )
+    }
+}
+
+impl Backend for Resolvconf {
+    fn name(&self) -> &'static str {
+        "resolvconf"
+    }
+
+    fn activate(&self, system: &System, profile: &Profile) -> Result<(), Error> {
+        let path = get_path();
+        let content = system
+            .read(&path)?
+            .unwrap_or_else(|| String::from(DEFAULT_TEMPLATE));
+        write_head_file(system, &path, &with_dns(&content, profile))?;
+        update_resolvconf(system)?;
+        // Unless live, resolv.conf is not regenerated, there is nothing to check.
+        match system.is_live() {
+            true => verify(system, &profile.nameservers()),
+            false => Ok(()),
+        }
+    }
+
+    fn deactivate(&self, system: &System) -> Result<(), Error> {
+        let path = get_path();
+        if let Some(content) = system.read(&path)? {
+            let deactivated = without_dns(&content);
+            if deactivated != content {
+                write_head_file(system, &path, &deactivated)?;
+                update_resolvconf(system)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The managed block, or the lines the tool wrote before it had one.
+    fn configured(&self, system: &System) -> Result<Vec<Configured>, Error> {
+        let path = get_path();
+        let body = system.read(&path)?.and_then(|content| {
+            block::extract(&content).or_else(|| {
+                content
+                    .contains(LEGACY_ADGUARD_DNS_SERVER_CONFIG)
+                    .then(|| LEGACY_ADGUARD_DNS_SERVER_CONFIG.to_string())
+            })
+        });
+
+        let Some(body) = body else {
+            return Ok(Vec::new());
+        };
+        Ok(vec![Configured {
+            path: system.path(&path)?,
+            nameservers: ResolvConf::parse(&body).nameservers().copied().collect(),
+        }])
+    }
+}
+
+pub fn get_path() -> String {
+    match env::var(RESOLVCONF_HEAD_ENV_VAR) {
+        Ok(value) => value,
+        Err(_) => String::from(RESOLVCONF_HEAD_DEFAULT_PATH),
+    }
+}
+
+pub fn backups_dir(system: &System) -> Result<PathBuf, Error> {
+    system.path(system.state_dir().join("backups"))
+}
+
+/// Puts a backup back in place of the head file, the most recent one when
+/// `id` is `None`. The current head file is backed up first unless it is
+/// the same, so restoring the same backup again changes nothing.
+pub fn restore(system: &System, id: Option<&str>) -> Result<Backup, Error> {
+    let backup = backup::find(&backups_dir(system)?, id)?;
+    let content = std::fs::read_to_string(&backup.path)?;
+    write_head_file(system, &get_path(), &content)?;
+    update_resolvconf(system)?;
+    Ok(backup)
+}
+
+/// Replaces the head file, saving a backup of its current content first.
+fn write_head_file(system: &System, path: &str, content: &str) -> Result<(), Error> {
+    if let Some(current) = system.read(path)? {
+        if current == content {
+            return Ok(());
+        }
+        if system.is_dry_run() {
+            return system.write(Path::new(path), content);
+        }
+        let backup = backup::create(&backups_dir(system)?, &current)?;
+        system.note(Note::Info(format!(
+            "Saved backup {} of {}",
+            backup.id, path
+        )));
+    }
+
+    system.write(Path::new(path), content)
+}
+
+fn with_dns(content: &str, profile: &Profile) -> String {
+    block::insert(&without_legacy_config(content), &profile.config())
+}
+
+fn without_dns(content: &str) -> String {
+    block::remove(&without_legacy_config(content))
+}
+
+fn without_legacy_config(content: &str) -> String {
+    content.replace(LEGACY_ADGUARD_DNS_SERVER_CONFIG, "")
+}
+
+/// Checks that the resolver picked up the head file: the generated
+/// resolv.conf has to list `expected` first, in order.
+fn verify(system: &System, expected: &[IpAddr]) -> Result<(), Error> {
+    let content = system.read(RESOLV_CONF_PATH)?.unwrap_or_default();
+    let found: Vec<IpAddr> = ResolvConf::parse(&content).nameservers().copied().collect();
+
+    if found.starts_with(expected) {
+        return Ok(());
+    }
+    Err(Error::VerificationFailed(NotApplied {
+        expected: expected.to_vec(),
+        found,
+    }))
+}
+
+/// Regenerates resolv.conf. resolvconf reports problems with its update
+/// scripts on stderr without failing, those are noted as warnings.
+fn update_resolvconf(system: &System) -> Result<(), Error> {
+    let output = system
+        .reload("resolvconf", &["-u"])
+        .map_err(|err| match err {
+            Error::BackendMissing { program, .. } => Error::BackendMissing {
+                program,
+                hint: Some("use `--backend resolv-conf` to write /etc/resolv.conf directly"),
+            },
+            _ => err,
+        })?;
+
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        system.note(Note::Warning(format!("resolvconf: {}", line.trim())));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{self, IpFamily};
+    use crate::testutil::{self, TempDir};
+
+    /// Installs a `resolvconf` generating resolv.conf from the head file,
+    /// then running `script`.
+    fn fake_resolvconf(system: &System, root: &Path, script: &str) -> PathBuf {
+        testutil::fake_command(
+            root,
+            "resolvconf",
+            &format!(
+                "cat '{}' > '{}'\n{}",
+                system.path(get_path()).unwrap().display(),
+                system.path(RESOLV_CONF_PATH).unwrap().display(),
+                script
+            ),
+        )
+    }
+
+    #[test]
+    fn restoring_backs_up_the_head_file_once() -> Result<(), Error> {
+        let root = TempDir::new("resolvconf-restore");
+        let system = System::with_root(root.path(), None);
+        let system = System::with_root(
+            root.path(),
+            Some(&fake_resolvconf(&system, root.path(), "")),
+        );
+        system.write(get_path(), "# original\n")?;
+        Resolvconf.activate(&system, &testutil::profile("quad9"))?;
+        let quad9 = system.read(get_path())?.unwrap();
+        Resolvconf.activate(&system, &testutil::profile("cloudflare"))?;
+        let notes = system.take_notes();
+        assert_eq!(notes.len(), 2);
+        assert!(notes[0].to_string().starts_with("Saved backup "));
+
+        let cloudflare = system.read(get_path())?.unwrap();
+
+        let restored = restore(&system, None)?;
+        assert_eq!(system.read(get_path())?.unwrap(), quad9);
+        assert_eq!(backup::list(&backups_dir(&system)?)?.len(), 3);
+        assert_eq!(restore(&system, Some(&restored.id))?, restored);
+        assert_eq!(system.read(get_path())?.unwrap(), quad9);
+        assert_eq!(backup::list(&backups_dir(&system)?)?.len(), 3);
+
+        restore(&system, None)?;
+        assert_eq!(system.read(get_path())?.unwrap(), cloudflare);
+        Ok(())
+    }
+
+    #[test]
+    fn get_path_function_returns_the_resolvconf_head_env_var_value_if_it_is_set() {
+        if let Ok(value) = env::var(RESOLVCONF_HEAD_ENV_VAR) {
+            assert_eq!(get_path(), value)
+        } else {
+            assert_eq!(get_path(), RESOLVCONF_HEAD_DEFAULT_PATH)
+        }
+    }
+
+    #[test]
+    fn activate_dns_test() {
+        let profile = testutil::profile(provider::DEFAULT_PROVIDER);
+        let content = format!("{}search example.org\n", DEFAULT_TEMPLATE);
+
+        let content = with_dns(&content, &profile);
+
+        assert!(content.starts_with(DEFAULT_TEMPLATE));
+        assert!(content.contains("search example.org\n"));
+        assert!(content.contains(&format!(
+            "{}\n{}{}\n",
+            block::BEGIN_MARKER,
+            profile.provider.config(IpFamily::V4),
+            block::END_MARKER
+        )));
+    }
+
+    #[test]
+    fn deactivate_dns_test() {
+        let profile = testutil::profile(provider::DEFAULT_PROVIDER);
+        let content = format!("{}search example.org\n", DEFAULT_TEMPLATE);
+
+        assert_eq!(without_dns(&with_dns(&content, &profile)), content);
+    }
+
+    #[test]
+    fn legacy_config_is_replaced_by_managed_block() {
+        let profile = testutil::profile("quad9");
+        let legacy = format!("{}{}", DEFAULT_TEMPLATE, LEGACY_ADGUARD_DNS_SERVER_CONFIG);
+
+        let content = with_dns(&legacy, &profile);
+
+        assert!(!content.contains("94.149.15.15"));
+        assert_eq!(without_dns(&content), DEFAULT_TEMPLATE);
+    }
+
+    #[test]
+    fn activate_and_deactivate_update_resolvconf() -> Result<(), Error> {
+        let root = TempDir::new("resolvconf-backend");
+        let bin_dir = fake_resolvconf(&System::with_root(root.path(), None), root.path(), "");
+        let system = System::with_root(root.path(), Some(&bin_dir));
+
+        Resolvconf.activate(&system, &testutil::profile("quad9"))?;
+
+        let configured = Resolvconf.configured(&system)?;
+        assert_eq!(configured.len(), 1);
+        assert_eq!(
+            configured[0].nameservers,
+            testutil::profile("quad9").nameservers()
+        );
+        assert_eq!(testutil::command_log(root.path(), "resolvconf"), "-u\n");
+
+        Resolvconf.deactivate(&system)?;
+        Resolvconf.deactivate(&system)?;
+
+        assert!(Resolvconf.configured(&system)?.is_empty());
+        assert_eq!(
+            system.read(get_path())?.unwrap(),
+            DEFAULT_TEMPLATE,
+            "the head file is kept without the block"
+        );
+        assert_eq!(testutil::command_log(root.path(), "resolvconf"), "-u\n-u\n");
+        Ok(())
+    }
+
+    #[test]
+    fn warnings_of_resolvconf_are_not_errors() -> Result<(), Error> {
+        let root = TempDir::new("resolvconf-warnings");
+        let system = System::with_root(root.path(), None);
+        let bin_dir = fake_resolvconf(
+            &system,
+            root.path(),
+            "echo '/etc/resolvconf/update.d/libc: Warning: /etc/resolv.conf is not a symbolic link' >&2",
+        );
+        let system = System::with_root(root.path(), Some(&bin_dir));
+
+        Resolvconf.activate(&system, &testutil::profile("quad9"))?;
+
+        assert_eq!(
+            Resolvconf.configured(&system)?[0].nameservers,
+            testutil::profile("quad9").nameservers()
+        );
+        assert_eq!(
+            system.take_notes(),
+            [Note::Warning(String::from(
+                "resolvconf: /etc/resolvconf/update.d/libc: Warning: /etc/resolv.conf is not a symbolic link"
+            ))]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn change_not_picked_up_by_the_resolver_is_rolled_back() -> Result<(), Error> {
+        let root = TempDir::new("resolvconf-not-applied");
+        let system = System::with_root(root.path(), None);
+        let bin_dir = fake_resolvconf(
+            &system,
+            root.path(),
+            &format!(
+                "echo 'nameserver 127.0.0.53' > '{}'",
+                system.path(RESOLV_CONF_PATH)?.display()
+            ),
+        );
+        let system = System::with_root(root.path(), Some(&bin_dir));
+        system.write(get_path(), DEFAULT_TEMPLATE)?;
+
+        let err = system
+            .transaction(|| Resolvconf.activate(&system, &testutil::profile("quad9")))
+            .unwrap_err();
+
+        let Error::VerificationFailed(not_applied) = err else {
+            panic!("not a verification failure")
+        };
+        assert_eq!(
+            not_applied,
+            NotApplied {
+                expected: testutil::profile("quad9").nameservers(),
+                found: vec!["127.0.0.53".parse().unwrap()],
+            }
+        );
+        assert_eq!(system.read(get_path())?.unwrap(), DEFAULT_TEMPLATE);
+        assert_eq!(testutil::command_log(root.path(), "resolvconf"), "-u\n-u\n");
+        Ok(())
+    }
+
+    #[test]
+    fn failed_update_is_rolled_back() -> Result<(), Error> {
+        let root = TempDir::new("resolvconf-failed");
+        let system = System::with_root(root.path(), None);
+        let bin_dir = fake_resolvconf(
+            &system,
+            root.path(),
+            &format!(
+                "grep -q BEGIN '{}' && echo 'update failed' >&2 && exit 1; exit 0",
+                system.path(get_path())?.display()
+            ),
+        );
+        let system = System::with_root(root.path(), Some(&bin_dir));
+
+        let err = system
+            .transaction(|| Resolvconf.activate(&system, &testutil::profile("quad9")))
+            .unwrap_err();
+
+        assert!(err.to_string().contains("update failed"));
+        assert_eq!(system.read(get_path())?, None);
+        Ok(())
+    }
+}
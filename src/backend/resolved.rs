@@ -0,0 +1,182 @@
+use super::{Backend, Configured};
+use crate::error::Error;
+use crate::provider::Profile;
+use crate::system::System;
+
+const DROP_IN_PATH: &str = "/etc/systemd/resolved.conf.d/cfg-adguard-dns.conf";
+
+/// Configures systemd-resolved with a resolved.conf(5) drop-in. `Domains=~.`
+/// routes every query to these servers rather than to the ones each link
+/// learned through DHCP, and `FallbackDNS=` replaces the compiled-in
+/// fallback servers.
+pub struct Resolved;
+
+impl Backend for Resolved {
+    fn name(&self) -> &'static str {
+        "systemd-resolved"
+    }
+
+    fn supports_encryption(&self) -> bool {
+        true
+    }
+
+    fn activate(&self, system: &System, profile: &Profile) -> Result<(), Error> {
+        system.write(DROP_IN_PATH, &drop_in(profile))?;
+        reload(system)
+    }
+
+    fn deactivate(&self, system: &System) -> Result<(), Error> {
+        if system.remove(DROP_IN_PATH)? {
+            reload(system)?;
+        }
+        Ok(())
+    }
+
+    fn configured(&self, system: &System) -> Result<Vec<Configured>, Error> {
+        let content = match system.read(DROP_IN_PATH)? {
+            Some(content) => content,
+            None => return Ok(Vec::new()),
+        };
+
+        let nameservers = content
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("DNS="))
+            .flat_map(str::split_whitespace)
+            .filter_map(|server| server.split('#').next()?.parse().ok())
+            .collect();
+
+        Ok(vec![Configured {
+            path: system.path(DROP_IN_PATH)?,
+            nameservers,
+        }])
+    }
+}
+
+fn drop_in(profile: &Profile) -> String {
+    let servers: Vec<_> = profile
+        .nameservers()
+        .iter()
+        .map(|addr| match profile.encrypted {
+            true => format!("{}#{}", addr, profile.tls_host()),
+            false => addr.to_string(),
+        })
+        .collect();
+    let servers = servers.join(" ");
+
+    let mut content = format!(
+        "# Managed by cfg-adguard-dns, removed by `cfg-adguard-dns deactivate`
+# {}
+# {}
+[Resolve]
+DNS={}
+FallbackDNS={}
+Domains=~.
+",
+        profile.provider.label, profile.provider.url, servers, servers
+    );
+    if profile.encrypted {
+        content.push_str("DNSOverTLS=yes\n");
+    }
+    content
+}
+
+fn reload(system: &System) -> Result<(), Error> {
+    system.reload("systemctl", &["reload-or-restart", "systemd-resolved"])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::IpFamily;
+    use crate::testutil::{self, TempDir};
+
+    #[test]
+    fn drop_in_lists_the_profile_nameservers() {
+        let profile = Profile {
+            family: IpFamily::Both,
+            ..testutil::profile("adguard")
+        };
+
+        let content = drop_in(&profile);
+
+        assert!(content.contains("[Resolve]\n"));
+        assert!(content.contains("\nDNS=94.140.14.14 2a10:50c0::ad1:ff 94.140.15.15\n"));
+        assert!(content.contains("\nFallbackDNS=94.140.14.14 2a10:50c0::ad1:ff 94.140.15.15\n"));
+        assert!(content.contains("\nDomains=~.\n"));
+        assert!(!content.contains("DNSOverTLS"));
+    }
+
+    #[test]
+    fn encrypted_drop_in_names_the_tls_servers() -> Result<(), Error> {
+        let root = TempDir::new("resolved-encrypted");
+        let bin_dir = testutil::fake_command(root.path(), "systemctl", "");
+        let system = System::with_root(root.path(), Some(&bin_dir));
+        let profile = Profile {
+            encrypted: true,
+            ..testutil::profile("adguard")
+        };
+
+        Resolved.activate(&system, &profile)?;
+
+        let content = system.read(DROP_IN_PATH)?.unwrap();
+        assert!(content
+            .contains("\nDNS=94.140.14.14#dns.adguard-dns.com 94.140.15.15#dns.adguard-dns.com\n"));
+        assert!(content.ends_with("\nDNSOverTLS=yes\n"));
+        assert_eq!(
+            Resolved.configured(&system)?[0].nameservers,
+            profile.nameservers()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn client_id_is_part_of_the_tls_server_name() {
+        let profile = Profile {
+            encrypted: true,
+            client_id: Some(String::from("laptop")),
+            ..testutil::profile("adguard-private")
+        };
+
+        assert!(drop_in(&profile).contains(
+            "\nDNS=94.140.14.49#laptop.d.adguard-dns.com 94.140.14.59#laptop.d.adguard-dns.com\n"
+        ));
+    }
+
+    #[test]
+    fn activate_writes_drop_in_and_deactivate_removes_it() -> Result<(), Error> {
+        let root = TempDir::new("resolved-backend");
+        let bin_dir = testutil::fake_command(root.path(), "systemctl", "");
+        let system = System::with_root(root.path(), Some(&bin_dir));
+        let profile = testutil::profile("quad9");
+
+        Resolved.activate(&system, &profile)?;
+
+        let configured = Resolved.configured(&system)?;
+        assert_eq!(configured[0].nameservers, profile.nameservers());
+        assert!(root
+            .path()
+            .join("etc/systemd/resolved.conf.d/cfg-adguard-dns.conf")
+            .exists());
+
+        Resolved.deactivate(&system)?;
+        Resolved.deactivate(&system)?;
+
+        assert!(Resolved.configured(&system)?.is_empty());
+        assert_eq!(
+            testutil::command_log(root.path(), "systemctl"),
+            "reload-or-restart systemd-resolved\nreload-or-restart systemd-resolved\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn failed_reload_is_reported() {
+        let root = TempDir::new("resolved-backend-fail");
+        let bin_dir = testutil::fake_command(root.path(), "systemctl", "exit 1");
+        let system = System::with_root(root.path(), Some(&bin_dir));
+        let profile = testutil::profile("quad9");
+
+        assert!(Resolved.activate(&system, &profile).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::fsutil;
+
+/// Only the most recent backups are kept, older ones are pruned on creation.
+pub const MAX_BACKUPS: usize = 20;
+
+#[derive(Debug, PartialEq)]
+pub struct Backup {
+    pub id: String,
+    pub path: PathBuf,
+}
+
+/// Stores `content` as a new backup in `dir`.
+pub fn create(dir: &Path, content: &str) -> Result<Backup, Error> {
+    fs::create_dir_all(dir)?;
+
+    // Backups taken within the same second are numbered after the last one,
+    // pruned ids are never reused so the order is kept.
+    let timestamp = format_timestamp(now());
+    let counter = list(dir)?
+        .iter()
+        .filter_map(|backup| parse_id(&backup.id))
+        .filter(|(other, _)| *other == timestamp)
+        .map(|(_, counter)| counter + 1)
+        .max();
+    let id = match counter {
+        Some(counter) => format!("{}-{}", timestamp, counter),
+        None => timestamp,
+    };
+
+    let path = dir.join(&id);
+    fsutil::write_atomic(&path, content)?;
+    prune(dir)?;
+
+    Ok(Backup { id, path })
+}
+
+/// Lists the backups in `dir`, oldest first.
+pub fn list(dir: &Path) -> Result<Vec<Backup>, Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut backups = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if let Some(id) = entry.file_name().to_str() {
+            if parse_id(id).is_some() {
+                backups.push(Backup {
+                    id: id.to_string(),
+                    path: entry.path(),
+                });
+            }
+        }
+    }
+    backups.sort_by(|a, b| parse_id(&a.id).cmp(&parse_id(&b.id)));
+
+    Ok(backups)
+}
+
+/// Finds the backup with the given id, or the most recent one when `id` is `None`.
+pub fn find(dir: &Path, id: Option<&str>) -> Result<Backup, Error> {
+    let backups = list(dir)?;
+    let backup = match id {
+        Some(id) => backups.into_iter().find(|backup| backup.id == id),
+        None => backups.into_iter().last(),
+    };
+
+    backup.ok_or_else(|| match id {
+        Some(id) => Error::new(ErrorKind::NotFound, format!("no backup with id `{}`", id)),
+        None => Error::new(ErrorKind::NotFound, "no backup available"),
+    })
+}
+
+fn prune(dir: &Path) -> Result<(), Error> {
+    let backups = list(dir)?;
+    if backups.len() > MAX_BACKUPS {
+        for backup in &backups[..backups.len() - MAX_BACKUPS] {
+            fs::remove_file(&backup.path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Splits an id such as `20240131T235959Z-2` into its sortable parts.
+fn parse_id(id: &str) -> Option<(&str, u32)> {
+    let (timestamp, counter) = match id.split_once('-') {
+        Some((timestamp, counter)) => (timestamp, counter.parse().ok()?),
+        None => (id, 0),
+    };
+
+    let valid = timestamp.len() == 16
+        && timestamp.char_indices().all(|(i, c)| match i {
+            8 => c == 'T',
+            15 => c == 'Z',
+            _ => c.is_ascii_digit(),
+        });
+    valid.then_some((timestamp, counter))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Formats seconds since the epoch as a UTC `YYYYMMDDTHHMMSSZ` timestamp.
+fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let secs_of_day = secs % 86_400;
+
+    // Civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn format_timestamp_test() {
+        assert_eq!(format_timestamp(0), "19700101T000000Z");
+        assert_eq!(format_timestamp(951_782_400), "20000229T000000Z");
+        assert_eq!(format_timestamp(1_706_745_599), "20240131T235959Z");
+    }
+
+    #[test]
+    fn backups_are_listed_oldest_first_and_restorable_by_id() -> Result<(), Error> {
+        let dir = TempDir::new("backups");
+
+        let first = create(dir.path(), "first")?;
+        let second = create(dir.path(), "second")?;
+
+        assert_eq!(list(dir.path())?, vec![first, second]);
+        let latest = find(dir.path(), None)?;
+        assert_eq!(fs::read_to_string(latest.path)?, "second");
+        let oldest = find(dir.path(), Some(&list(dir.path())?[0].id))?;
+        assert_eq!(fs::read_to_string(oldest.path)?, "first");
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_files_and_ids_are_ignored() -> Result<(), Error> {
+        let dir = TempDir::new("backups-unknown");
+        fs::write(dir.path().join("notes.txt"), "")?;
+
+        assert!(list(dir.path())?.is_empty());
+        assert!(find(dir.path(), Some("../head")).is_err());
+        assert!(find(&dir.path().join("missing"), None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn old_backups_are_pruned() -> Result<(), Error> {
+        let dir = TempDir::new("backups-prune");
+
+        for i in 0..MAX_BACKUPS + 2 {
+            create(dir.path(), &i.to_string())?;
+        }
+
+        let backups = list(dir.path())?;
+        assert_eq!(backups.len(), MAX_BACKUPS);
+        assert_eq!(fs::read_to_string(&backups[0].path)?, "2");
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+pub const BEGIN_MARKER: &str = "# BEGIN cfg-adguard-dns";
+pub const END_MARKER: &str = "# END cfg-adguard-dns";
+
+/// Locates the managed block as a `(start, end)` line range, `end` being the
+/// index of the END marker. An unterminated block is treated as absent so
+/// that lines written by the user are never swallowed.
+fn find(lines: &[&str]) -> Option<(usize, usize)> {
+    let start = lines.iter().position(|line| line.trim() == BEGIN_MARKER)?;
+    let end = lines[start..]
+        .iter()
+        .position(|line| line.trim() == END_MARKER)?;
+    Some((start, start + end))
+}
+
+/// Returns the lines between the markers, if `content` has a managed block.
+pub fn extract(content: &str) -> Option<String> {
+    let lines: Vec<_> = content.lines().collect();
+    let (start, end) = find(&lines)?;
+    Some(join(&lines[start + 1..end]))
+}
+
+/// Returns `content` with its managed block replaced by `body`. The block is
+/// appended at the end when `content` has none yet.
+pub fn insert(content: &str, body: &str) -> String {
+    let lines: Vec<_> = content.lines().collect();
+    let block = format!("{}\n{}{}\n", BEGIN_MARKER, body, END_MARKER);
+
+    match find(&lines) {
+        Some((start, end)) => {
+            let mut result = join(&lines[..start]);
+            result.push_str(&block);
+            result.push_str(&join(&lines[end + 1..]));
+            result
+        }
+        None => {
+            let mut result = join(&lines);
+            result.push_str(&block);
+            result
+        }
+    }
+}
+
+/// Returns `content` without its managed block, leaving every other line untouched.
+pub fn remove(content: &str) -> String {
+    let lines: Vec<_> = content.lines().collect();
+
+    match find(&lines) {
+        Some((start, end)) => {
+            let mut result = join(&lines[..start]);
+            result.push_str(&join(&lines[end + 1..]));
+            result
+        }
+        None => content.to_string(),
+    }
+}
+
+fn join(lines: &[&str]) -> String {
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER_CONTENT: &str = "search example.org\noptions edns0\n";
+
+    #[test]
+    fn insert_appends_block_and_keeps_user_lines() {
+        let content = insert(USER_CONTENT, "nameserver 9.9.9.9\n");
+
+        assert_eq!(
+            content,
+            "search example.org\noptions edns0\n# BEGIN cfg-adguard-dns\nnameserver 9.9.9.9\n# END cfg-adguard-dns\n"
+        );
+    }
+
+    #[test]
+    fn insert_replaces_existing_block_in_place() {
+        let content = format!(
+            "search example.org\n{}\nnameserver 9.9.9.9\n{}\noptions edns0\n",
+            BEGIN_MARKER, END_MARKER
+        );
+
+        let content = insert(&content, "nameserver 1.1.1.1\n");
+
+        assert_eq!(
+            content,
+            "search example.org\n# BEGIN cfg-adguard-dns\nnameserver 1.1.1.1\n# END cfg-adguard-dns\noptions edns0\n"
+        );
+        assert_eq!(insert(&content, "nameserver 1.1.1.1\n"), content);
+    }
+
+    #[test]
+    fn remove_only_drops_the_block() {
+        let content = insert(USER_CONTENT, "nameserver 9.9.9.9\n");
+
+        assert_eq!(remove(&content), USER_CONTENT);
+        assert_eq!(remove(USER_CONTENT), USER_CONTENT);
+    }
+
+    #[test]
+    fn unterminated_block_is_left_alone() {
+        let content = format!("{}\nnameserver 9.9.9.9\n", BEGIN_MARKER);
+
+        assert_eq!(remove(&content), content);
+        assert!(extract(&content).is_none());
+    }
+
+    #[test]
+    fn extract_returns_block_body() {
+        let content = insert(USER_CONTENT, "nameserver 9.9.9.9\n");
+
+        assert_eq!(extract(&content).unwrap(), "nameserver 9.9.9.9\n");
+    }
+}
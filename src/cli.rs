@@ -0,0 +1,664 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use cfg_adguard_dns::backend::{self, Backend};
+use cfg_adguard_dns::provider::{self, IpFamily, Profile, Provider};
+use cfg_adguard_dns::proxy::{self, Transport};
+use cfg_adguard_dns::Error;
+
+pub const HELP_MESSAGE: &str = "
+Usage: sudo cfg-adguard-dns <command> [options...]
+
+Commands:
+        activate [options...]           Activate a DNS provider
+            --provider <name>           DNS provider to use (default: adguard)
+            --ip-family v4|v6|both      Nameserver addresses to write (default: v4)
+            --backend <name>            Resolver stack to configure (default: auto)
+            --interface <name>          Interface to configure, may be repeated (netplan only)
+            --encrypted                 Use DNS-over-TLS (systemd-resolved and unbound only)
+            --via-proxy                 Point the resolver at `cfg-adguard-dns proxy` instead
+            --client-id <id>            Device name for AdGuard DNS private servers (with --encrypted)
+            --dry-run                   Show the changes and commands without making them
+        deactivate [options...]         Deactivate the configured DNS provider (default: everywhere)
+            --backend <name>            Resolver stack to deactivate
+            --dry-run                   Show the changes and commands without making them
+        status                          Shows which DNS provider is activated, if any
+        backend                         Shows which backend `auto` picks, and why
+        providers                       List the available DNS providers
+        backups [list]                  List the backups of the head file
+        restore [<id>] [--dry-run]      Restore a backup of the head file (default: the latest)
+        proxy [options...]              Forward plain DNS queries over an encrypted transport
+            --provider <name>           DNS provider to forward to (default: adguard)
+            --transport tls|https       DNS-over-TLS or DNS-over-HTTPS (default: tls)
+            --listen <addr:port>        Address to listen on (default: 127.0.0.153:53)
+            --client-id <id>            Device name for AdGuard DNS private servers
+        help                            Display the current help message
+
+Options:
+        --root <dir>                    Configure the system mounted at <dir>, e.g. a chroot,
+                                        without reloading its services
+
+Commands may also be given as options, e.g. `--activate` or `--status`.
+
+Backends:
+        auto                            Detect the one that takes effect on this system
+        resolvconf                      The resolvconf head file
+        systemd-resolved                A drop-in in /etc/systemd/resolved.conf.d
+        networkmanager                  The keyfiles of the active NetworkManager connections
+        netplan                         An overlay in /etc/netplan, for Ubuntu servers
+        dhclient                        A `supersede` statement in /etc/dhcp/dhclient.conf
+        dhcpcd                          A `static` option in /etc/dhcpcd.conf
+        dnsmasq                         Forwarders in /etc/dnsmasq.d
+        unbound                         A forward zone in /etc/unbound/unbound.conf.d
+        resolv-conf                     /etc/resolv.conf itself, for systems without resolvconf
+
+The nameservers are written between `# BEGIN cfg-adguard-dns` and `# END cfg-adguard-dns`
+in /etc/resolvconf/resolv.conf.d/head; lines outside of this block are left untouched.
+A backup of the head file is saved in /var/lib/cfg-adguard-dns/backups before it is changed.
+";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    /// `backend` is `None` when it is to be detected.
+    Activate {
+        profile: Profile,
+        backend: Option<&'static dyn Backend>,
+        dry_run: bool,
+    },
+    Deactivate {
+        backend: Option<&'static dyn Backend>,
+        dry_run: bool,
+    },
+    Status,
+    Backend,
+    Providers,
+    Backups,
+    Restore {
+        id: Option<String>,
+        dry_run: bool,
+    },
+    /// `provider` and `client_id` are `None` when not given on the command
+    /// line, the configuration file then deciding.
+    Proxy {
+        provider: Option<&'static Provider>,
+        transport: Transport,
+        client_id: Option<String>,
+        listen: SocketAddr,
+    },
+}
+
+impl Command {
+    /// Whether the command is to show its changes rather than make them.
+    pub fn dry_run(&self) -> bool {
+        match self {
+            Command::Activate { dry_run, .. }
+            | Command::Deactivate { dry_run, .. }
+            | Command::Restore { dry_run, .. } => *dry_run,
+            _ => false,
+        }
+    }
+}
+
+/// Reported when the command line cannot be parsed; the binary exits with status 2.
+#[derive(Debug, PartialEq)]
+pub struct UsageError(String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<UsageError> for Error {
+    fn from(err: UsageError) -> Error {
+        Error::Usage(err.0)
+    }
+}
+
+/// Takes `--root <dir>`, which may be given anywhere, out of the arguments
+/// following the program name.
+pub fn take_root(args: &[String]) -> Result<(Option<PathBuf>, Vec<String>), UsageError> {
+    let mut root = None;
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match split_option(arg) {
+            ("--root", inline_value) => {
+                root = Some(PathBuf::from(value("--root", inline_value, &mut args)?))
+            }
+            _ => rest.push(arg.clone()),
+        }
+    }
+    Ok((root, rest))
+}
+
+/// Parses the arguments following the program name.
+pub fn parse(args: &[String]) -> Result<Command, UsageError> {
+    let (name, options) = match args.split_first() {
+        Some((name, options)) => (name.as_str(), options),
+        None => return Ok(Command::Help),
+    };
+
+    if options
+        .iter()
+        .any(|option| option == "--help" || option == "-h")
+    {
+        return Ok(Command::Help);
+    }
+
+    match name.trim_start_matches("--") {
+        "help" | "-h" => no_options(name, options, Command::Help),
+        "activate" => parse_activate(options),
+        "deactivate" => parse_deactivate(options),
+        "status" => no_options(name, options, Command::Status),
+        "backend" => no_options(name, options, Command::Backend),
+        "providers" => no_options(name, options, Command::Providers),
+        "backups" => match options {
+            [list] if list == "list" => Ok(Command::Backups),
+            _ => no_options(name, options, Command::Backups),
+        },
+        "proxy" => parse_proxy(options),
+        "restore" => parse_restore(options),
+        _ => Err(UsageError(format!("Unknown command `{}`", name))),
+    }
+}
+
+fn parse_activate(options: &[String]) -> Result<Command, UsageError> {
+    let mut provider_name = None;
+    let mut client_id = None;
+    let mut family = IpFamily::default();
+    let mut backend = None;
+    let mut interfaces = Vec::new();
+    let mut encrypted = false;
+    let mut via_proxy = false;
+    let mut dry_run = false;
+    let mut options = options.iter();
+
+    while let Some(option) = options.next() {
+        let (flag, inline_value) = split_option(option);
+
+        match flag {
+            "--provider" => provider_name = Some(value(flag, inline_value, &mut options)?),
+            "--client-id" => {
+                client_id = Some(parse_client_id(value(flag, inline_value, &mut options)?)?)
+            }
+            "--ip-family" => {
+                let value = value(flag, inline_value, &mut options)?;
+                family = IpFamily::parse(value).ok_or_else(|| {
+                    UsageError(format!(
+                        "Invalid IP family `{}`, expected `v4`, `v6` or `both`",
+                        value
+                    ))
+                })?;
+            }
+            "--backend" => match value(flag, inline_value, &mut options)? {
+                "auto" => backend = None,
+                name => backend = Some(find_backend(name)?),
+            },
+            "--encrypted" if inline_value.is_none() => encrypted = true,
+            "--via-proxy" if inline_value.is_none() => via_proxy = true,
+            "--dry-run" if inline_value.is_none() => dry_run = true,
+            "--interface" => interfaces.push(value(flag, inline_value, &mut options)?.to_string()),
+            _ => return Err(unknown_option(option)),
+        }
+    }
+
+    if encrypted && via_proxy {
+        return Err(UsageError(String::from(
+            "`--encrypted` and `--via-proxy` cannot be combined, the proxy already encrypts the queries",
+        )));
+    }
+
+    if client_id.is_some() && via_proxy {
+        return Err(UsageError(String::from(
+            "The proxy sends the client ID, give `--client-id` to `cfg-adguard-dns proxy` instead",
+        )));
+    }
+    if client_id.is_some() && !encrypted {
+        return Err(UsageError(String::from(
+            "The client ID is only sent over DNS-over-TLS, add `--encrypted`",
+        )));
+    }
+
+    Ok(Command::Activate {
+        profile: Profile {
+            provider: select_provider(provider_name, client_id.as_deref())?,
+            family,
+            interfaces,
+            encrypted,
+            via_proxy,
+            client_id,
+        },
+        backend,
+        dry_run,
+    })
+}
+
+fn parse_deactivate(options: &[String]) -> Result<Command, UsageError> {
+    let mut backend = None;
+    let mut dry_run = false;
+    let mut options = options.iter();
+
+    while let Some(option) = options.next() {
+        let (flag, inline_value) = split_option(option);
+
+        match flag {
+            "--backend" => backend = Some(find_backend(value(flag, inline_value, &mut options)?)?),
+            "--dry-run" if inline_value.is_none() => dry_run = true,
+            _ => return Err(unknown_option(option)),
+        }
+    }
+
+    Ok(Command::Deactivate { backend, dry_run })
+}
+
+fn parse_restore(options: &[String]) -> Result<Command, UsageError> {
+    let mut id = None;
+    let mut dry_run = false;
+
+    for option in options {
+        match option.as_str() {
+            "--dry-run" => dry_run = true,
+            option if option.starts_with('-') => return Err(unknown_option(option)),
+            _ if id.is_some() => {
+                return Err(UsageError(String::from(
+                    "`restore` takes at most one backup id",
+                )))
+            }
+            _ => id = Some(option.to_string()),
+        }
+    }
+
+    Ok(Command::Restore { id, dry_run })
+}
+
+fn parse_proxy(options: &[String]) -> Result<Command, UsageError> {
+    let mut provider_name = None;
+    let mut client_id = None;
+    let mut transport = Transport::default();
+    let mut listen = proxy::DEFAULT_LISTEN_ADDR;
+    let mut options = options.iter();
+
+    while let Some(option) = options.next() {
+        let (flag, inline_value) = split_option(option);
+
+        match flag {
+            "--provider" => provider_name = Some(value(flag, inline_value, &mut options)?),
+            "--client-id" => {
+                client_id = Some(parse_client_id(value(flag, inline_value, &mut options)?)?)
+            }
+            "--transport" => {
+                let value = value(flag, inline_value, &mut options)?;
+                transport = Transport::parse(value).ok_or_else(|| {
+                    UsageError(format!(
+                        "Invalid transport `{}`, expected `tls` or `https`",
+                        value
+                    ))
+                })?;
+            }
+            "--listen" => {
+                let value = value(flag, inline_value, &mut options)?;
+                listen = value.parse().map_err(|_| {
+                    UsageError(format!(
+                        "Invalid address `{}`, expected e.g. `127.0.0.153:53`",
+                        value
+                    ))
+                })?;
+            }
+            _ => return Err(unknown_option(option)),
+        }
+    }
+
+    Ok(Command::Proxy {
+        provider: match (provider_name, &client_id) {
+            (None, None) => None,
+            _ => Some(select_provider(provider_name, client_id.as_deref())?),
+        },
+        transport,
+        client_id,
+        listen,
+    })
+}
+
+fn parse_client_id(value: &str) -> Result<String, UsageError> {
+    match provider::is_valid_client_id(value) {
+        true => Ok(value.to_string()),
+        false => Err(UsageError(format!(
+            "Invalid client ID `{}`, expected lowercase letters, digits and dashes",
+            value
+        ))),
+    }
+}
+
+/// Finds the provider named on the command line, defaulting to the one
+/// serving client IDs when a client ID is given.
+fn select_provider(
+    name: Option<&str>,
+    client_id: Option<&str>,
+) -> Result<&'static Provider, UsageError> {
+    let provider = match (name, client_id) {
+        (Some(name), _) => find_provider(name)?,
+        (None, Some(_)) => find_provider(provider::CLIENT_ID_PROVIDER)?,
+        (None, None) => find_provider(provider::DEFAULT_PROVIDER)?,
+    };
+    if client_id.is_some() && !provider.client_ids {
+        return Err(UsageError(format!(
+            "{} does not tell devices apart by client ID, use `--provider {}`",
+            provider.label,
+            provider::CLIENT_ID_PROVIDER
+        )));
+    }
+    Ok(provider)
+}
+
+fn find_provider(name: &str) -> Result<&'static Provider, UsageError> {
+    provider::find(name).ok_or_else(|| {
+        UsageError(format!(
+            "Unknown provider `{}`. Try `cfg-adguard-dns providers` for the list of providers",
+            name
+        ))
+    })
+}
+
+fn find_backend(name: &str) -> Result<&'static dyn Backend, UsageError> {
+    backend::find(name).ok_or_else(|| {
+        UsageError(format!(
+            "Unknown backend `{}`. Try `cfg-adguard-dns --help` for the list of backends",
+            name
+        ))
+    })
+}
+
+/// Splits `--flag=value` into the flag and its value.
+fn split_option(option: &str) -> (&str, Option<&str>) {
+    match option.split_once('=') {
+        Some((flag, value)) => (flag, Some(value)),
+        None => (option, None),
+    }
+}
+
+fn value<'a>(
+    flag: &str,
+    inline_value: Option<&'a str>,
+    options: &mut impl Iterator<Item = &'a String>,
+) -> Result<&'a str, UsageError> {
+    match inline_value.or_else(|| options.next().map(String::as_str)) {
+        Some(value) if !value.is_empty() => Ok(value),
+        _ => Err(UsageError(format!("`{}` requires a value", flag))),
+    }
+}
+
+fn no_options(name: &str, options: &[String], command: Command) -> Result<Command, UsageError> {
+    match options.first() {
+        Some(option) => Err(UsageError(format!(
+            "`{}` does not take `{}`",
+            name.trim_start_matches("--"),
+            option
+        ))),
+        None => Ok(command),
+    }
+}
+
+fn unknown_option(option: &str) -> UsageError {
+    UsageError(format!("Unknown option `{}`", option))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn no_arguments_shows_help() {
+        assert_eq!(parse(&[]), Ok(Command::Help));
+        assert_eq!(parse(&args(&["activate", "--help"])), Ok(Command::Help));
+    }
+
+    #[test]
+    fn commands_are_accepted_with_or_without_dashes() {
+        assert_eq!(parse(&args(&["--status"])), Ok(Command::Status));
+        assert_eq!(parse(&args(&["status"])), Ok(Command::Status));
+        assert_eq!(
+            parse(&args(&["--deactivate"])),
+            Ok(Command::Deactivate {
+                backend: None,
+                dry_run: false
+            })
+        );
+    }
+
+    fn activate(provider: &str, family: IpFamily, backend: &str) -> Command {
+        Command::Activate {
+            profile: Profile {
+                family,
+                ..Profile::new(provider::find(provider).unwrap())
+            },
+            backend: backend::find(backend),
+            dry_run: false,
+        }
+    }
+
+    #[test]
+    fn activate_defaults_to_adguard() {
+        assert_eq!(
+            parse(&args(&["activate"])),
+            Ok(activate("adguard", IpFamily::V4, "auto"))
+        );
+    }
+
+    #[test]
+    fn activate_accepts_provider_in_both_forms() {
+        assert_eq!(
+            parse(&args(&["activate", "--provider", "quad9"])),
+            Ok(activate("quad9", IpFamily::V4, "auto"))
+        );
+        assert_eq!(
+            parse(&args(&["--activate", "--provider=quad9"])),
+            Ok(activate("quad9", IpFamily::V4, "auto"))
+        );
+    }
+
+    #[test]
+    fn activate_accepts_ip_family() {
+        assert_eq!(
+            parse(&args(&["activate", "--ip-family", "both"])),
+            Ok(activate("adguard", IpFamily::Both, "auto"))
+        );
+        assert!(parse(&args(&["activate", "--ip-family", "v5"])).is_err());
+    }
+
+    #[test]
+    fn backend_can_be_chosen() {
+        assert_eq!(
+            parse(&args(&["activate", "--backend", "systemd-resolved"])),
+            Ok(activate("adguard", IpFamily::V4, "systemd-resolved"))
+        );
+        assert_eq!(
+            parse(&args(&["deactivate", "--backend=systemd-resolved"])),
+            Ok(Command::Deactivate {
+                backend: backend::find("systemd-resolved"),
+                dry_run: false
+            })
+        );
+        assert_eq!(
+            parse(&args(&["activate", "--backend", "auto"])),
+            Ok(activate("adguard", IpFamily::V4, "auto"))
+        );
+        assert_eq!(parse(&args(&["backend"])), Ok(Command::Backend));
+        let Ok(Command::Activate {
+            profile, backend, ..
+        }) = parse(&args(&[
+            "activate",
+            "--backend=netplan",
+            "--interface",
+            "eth0",
+            "--interface=wlan0",
+        ]))
+        else {
+            panic!("not an activate command")
+        };
+        assert_eq!(backend, backend::find("netplan"));
+        assert_eq!(profile.interfaces, ["eth0", "wlan0"]);
+        assert!(!profile.encrypted);
+    }
+
+    #[test]
+    fn activate_accepts_encrypted() {
+        let Ok(Command::Activate { profile, .. }) = parse(&args(&["activate", "--encrypted"]))
+        else {
+            panic!("not an activate command")
+        };
+        assert!(profile.encrypted);
+        assert!(parse(&args(&["activate", "--encrypted=yes"])).is_err());
+        assert!(parse(&args(&["deactivate", "--backend", "unknown"])).is_err());
+    }
+
+    #[test]
+    fn activate_accepts_via_proxy() {
+        let Ok(Command::Activate { profile, .. }) = parse(&args(&["activate", "--via-proxy"]))
+        else {
+            panic!("not an activate command")
+        };
+        assert!(profile.via_proxy);
+        assert!(parse(&args(&["activate", "--via-proxy", "--encrypted"])).is_err());
+    }
+
+    #[test]
+    fn proxy_command() {
+        assert_eq!(
+            parse(&args(&["proxy"])),
+            Ok(Command::Proxy {
+                provider: None,
+                transport: Transport::Tls,
+                client_id: None,
+                listen: proxy::DEFAULT_LISTEN_ADDR,
+            })
+        );
+        assert_eq!(
+            parse(&args(&[
+                "proxy",
+                "--provider=quad9",
+                "--transport",
+                "https",
+                "--listen",
+                "127.0.0.1:5353"
+            ])),
+            Ok(Command::Proxy {
+                provider: provider::find("quad9"),
+                transport: Transport::Https,
+                client_id: None,
+                listen: "127.0.0.1:5353".parse().unwrap(),
+            })
+        );
+        assert!(parse(&args(&["proxy", "--transport", "quic"])).is_err());
+        assert!(parse(&args(&["proxy", "--listen", "localhost"])).is_err());
+    }
+
+    #[test]
+    fn client_id_selects_the_private_servers() {
+        let Ok(Command::Activate { profile, .. }) =
+            parse(&args(&["activate", "--encrypted", "--client-id", "laptop"]))
+        else {
+            panic!("not an activate command")
+        };
+        assert_eq!(profile.provider.name, provider::CLIENT_ID_PROVIDER);
+        assert_eq!(profile.client_id.as_deref(), Some("laptop"));
+
+        let Ok(Command::Proxy {
+            provider,
+            client_id,
+            ..
+        }) = parse(&args(&["proxy", "--client-id=laptop", "--transport=https"]))
+        else {
+            panic!("not a proxy command")
+        };
+        assert_eq!(provider.unwrap().name, provider::CLIENT_ID_PROVIDER);
+        assert_eq!(client_id.as_deref(), Some("laptop"));
+
+        for invalid in [
+            &["activate", "--client-id", "laptop"][..],
+            &["activate", "--via-proxy", "--client-id", "laptop"],
+            &["activate", "--encrypted", "--client-id", "Laptop"],
+            &[
+                "activate",
+                "--encrypted",
+                "--client-id",
+                "laptop",
+                "--provider",
+                "quad9",
+            ],
+            &["proxy", "--provider", "adguard", "--client-id", "laptop"],
+        ] {
+            assert!(parse(&args(invalid)).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn backups_and_restore_commands() {
+        assert_eq!(parse(&args(&["backups"])), Ok(Command::Backups));
+        assert_eq!(parse(&args(&["backups", "list"])), Ok(Command::Backups));
+        assert_eq!(
+            parse(&args(&["restore"])),
+            Ok(Command::Restore {
+                id: None,
+                dry_run: false
+            })
+        );
+        assert_eq!(
+            parse(&args(&["restore", "20240131T235959Z"])),
+            Ok(Command::Restore {
+                id: Some(String::from("20240131T235959Z")),
+                dry_run: false
+            })
+        );
+        assert!(parse(&args(&["restore", "a", "b"])).is_err());
+        assert!(parse(&args(&["restore", "--latest"])).is_err());
+    }
+
+    #[test]
+    fn mutating_commands_accept_dry_run() {
+        for command in [
+            &["activate", "--provider=quad9", "--dry-run"][..],
+            &["deactivate", "--dry-run", "--backend", "dnsmasq"],
+            &["restore", "--dry-run", "20240131T235959Z"],
+        ] {
+            let parsed = parse(&args(command)).unwrap();
+            assert!(parsed.dry_run(), "{:?}", command);
+        }
+        assert!(!parse(&args(&["activate"])).unwrap().dry_run());
+        assert!(parse(&args(&["status", "--dry-run"])).is_err());
+        assert!(parse(&args(&["activate", "--dry-run=yes"])).is_err());
+    }
+
+    #[test]
+    fn root_may_be_given_anywhere() {
+        assert_eq!(
+            take_root(&args(&["--root", "/mnt", "activate", "--dry-run"])),
+            Ok((
+                Some(PathBuf::from("/mnt")),
+                args(&["activate", "--dry-run"])
+            ))
+        );
+        assert_eq!(
+            take_root(&args(&["status", "--root=/mnt"])),
+            Ok((Some(PathBuf::from("/mnt")), args(&["status"])))
+        );
+        assert_eq!(take_root(&args(&["status"])), Ok((None, args(&["status"]))));
+        assert!(take_root(&args(&["status", "--root"])).is_err());
+    }
+
+    #[test]
+    fn usage_errors_are_reported() {
+        assert!(parse(&args(&["frobnicate"])).is_err());
+        assert!(parse(&args(&["status", "--provider", "quad9"])).is_err());
+        assert!(parse(&args(&["activate", "--provider"])).is_err());
+        assert!(parse(&args(&["activate", "--provider", "unknown"])).is_err());
+        assert!(parse(&args(&["activate", "--verbose"])).is_err());
+    }
+}
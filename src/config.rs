@@ -0,0 +1,123 @@
+use crate::error::Error;
+use crate::provider::{self, Provider};
+use crate::system::System;
+
+pub const CONFIG_PATH: &str = "/etc/cfg-adguard-dns.conf";
+
+/// Machine-wide settings, read from `key = value` lines of /etc/cfg-adguard-dns.conf.
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    /// The client ID of this device, used when the command line gives none.
+    pub client_id: Option<String>,
+    /// The provider `proxy` forwards to when the command line names none.
+    pub provider: Option<&'static Provider>,
+}
+
+impl Config {
+    /// The provider `proxy` forwards to without `--provider` or `--client-id`.
+    pub fn proxy_provider(&self) -> &'static Provider {
+        self.provider
+            .or_else(|| provider::find(provider::DEFAULT_PROVIDER))
+            .expect("the default provider exists")
+    }
+
+    /// The client ID to use with `provider`, if it tells devices apart.
+    pub fn client_id_for(&self, provider: &Provider) -> Option<String> {
+        self.client_id.clone().filter(|_| provider.client_ids)
+    }
+}
+
+/// Reads the configuration file, a missing one meaning the defaults.
+pub fn read(system: &System) -> Result<Config, Error> {
+    let content = system.read(CONFIG_PATH)?.unwrap_or_default();
+    parse(&content).or_else(|err| {
+        Err(Error::InvalidConfig(format!(
+            "{}: {}",
+            system.path(CONFIG_PATH)?.display(),
+            err
+        )))
+    })
+}
+
+fn parse(content: &str) -> Result<Config, String> {
+    let mut config = Config::default();
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected `<key> = <value>`", i + 1))?;
+        let value = value.trim();
+
+        match key.trim() {
+            "client-id" if provider::is_valid_client_id(value) => {
+                config.client_id = Some(value.to_string())
+            }
+            "client-id" => return Err(format!("line {}: invalid client ID `{}`", i + 1, value)),
+            "provider" => {
+                config.provider = Some(
+                    provider::find(value)
+                        .ok_or_else(|| format!("line {}: unknown provider `{}`", i + 1, value))?,
+                )
+            }
+            key => return Err(format!("line {}: unknown setting `{}`", i + 1, key)),
+        }
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn settings_are_parsed() {
+        assert_eq!(parse(""), Ok(Config::default()));
+        assert_eq!(
+            parse("# Team laptops\nclient-id = laptop-7\nprovider = quad9\n"),
+            Ok(Config {
+                client_id: Some(String::from("laptop-7")),
+                provider: provider::find("quad9"),
+            })
+        );
+        assert_eq!(
+            parse("provider = google\n"),
+            Err(String::from("line 1: unknown provider `google`"))
+        );
+        assert_eq!(
+            parse("client-id = Laptop 7\n"),
+            Err(String::from("line 1: invalid client ID `Laptop 7`"))
+        );
+        assert_eq!(
+            parse("\nclientid = laptop\n"),
+            Err(String::from("line 2: unknown setting `clientid`"))
+        );
+    }
+
+    #[test]
+    fn client_id_only_applies_to_providers_using_it() -> Result<(), Error> {
+        let root = TempDir::new("config");
+        let system = System::with_root(root.path(), None);
+        assert_eq!(read(&system)?, Config::default());
+        assert_eq!(
+            read(&system)?.proxy_provider().name,
+            provider::DEFAULT_PROVIDER
+        );
+
+        system.write(CONFIG_PATH, "client-id=laptop\n")?;
+        let config = read(&system)?;
+
+        let private = provider::find(provider::CLIENT_ID_PROVIDER).unwrap();
+        assert_eq!(config.client_id_for(private).as_deref(), Some("laptop"));
+        assert_eq!(
+            config.client_id_for(provider::find("adguard").unwrap()),
+            None
+        );
+        Ok(())
+    }
+}
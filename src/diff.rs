@@ -0,0 +1,168 @@
+//! Unified diffs, as printed by `diff -u`, to preview changes to files.
+
+/// Lines of unchanged content shown around each change.
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// One line of the edit script, with the index it has in the old and the
+/// new file, or would have for a line absent from it.
+#[derive(Clone, Copy)]
+struct Op {
+    kind: Kind,
+    old: usize,
+    new: usize,
+}
+
+/// Renders the changes turning `old` into `new`, empty when they are equal.
+/// A missing file is given as `None` and named `/dev/null`.
+pub fn unified(path: &str, old: Option<&str>, new: Option<&str>) -> String {
+    let old_lines: Vec<&str> = old.unwrap_or_default().lines().collect();
+    let new_lines: Vec<&str> = new.unwrap_or_default().lines().collect();
+    let ops = edit_script(&old_lines, &new_lines);
+
+    let changes: Vec<usize> = (0..ops.len())
+        .filter(|&i| ops[i].kind != Kind::Equal)
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    let mut diff = format!(
+        "--- {}\n+++ {}\n",
+        old.map_or("/dev/null", |_| path),
+        new.map_or("/dev/null", |_| path)
+    );
+
+    // Changes closer than twice the context share a hunk.
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for &i in &changes {
+        match groups.last_mut() {
+            Some((_, last)) if i - *last <= 2 * CONTEXT => *last = i,
+            _ => groups.push((i, i)),
+        }
+    }
+
+    for (first, last) in groups {
+        let start = first.saturating_sub(CONTEXT);
+        let end = (last + CONTEXT + 1).min(ops.len());
+        let hunk = &ops[start..end];
+
+        let old_count = hunk.iter().filter(|op| op.kind != Kind::Insert).count();
+        let new_count = hunk.iter().filter(|op| op.kind != Kind::Delete).count();
+        // An empty range starts at the line before it.
+        let old_start = hunk[0].old + usize::from(old_count > 0);
+        let new_start = hunk[0].new + usize::from(new_count > 0);
+        diff.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start, old_count, new_start, new_count
+        ));
+
+        for op in hunk {
+            let (prefix, line) = match op.kind {
+                Kind::Equal => (' ', old_lines[op.old]),
+                Kind::Delete => ('-', old_lines[op.old]),
+                Kind::Insert => ('+', new_lines[op.new]),
+            };
+            diff.push_str(&format!("{}{}\n", prefix, line));
+        }
+    }
+
+    diff
+}
+
+/// Finds the shortest edit script from the longest common subsequence of
+/// lines. Configuration files are small enough for the quadratic table.
+fn edit_script(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let (n, m) = (old.len(), new.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = match old[i] == new[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        let kind = if i < n && j < m && old[i] == new[j] {
+            Kind::Equal
+        } else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            Kind::Delete
+        } else {
+            Kind::Insert
+        };
+        ops.push(Op {
+            kind,
+            old: i,
+            new: j,
+        });
+        match kind {
+            Kind::Equal => (i, j) = (i + 1, j + 1),
+            Kind::Delete => i += 1,
+            Kind::Insert => j += 1,
+        }
+    }
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_are_shown_with_their_context() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\n";
+        let new = "a\nb\nc\nd\nE\nf\ng\nh\ni\nj\n";
+
+        assert_eq!(
+            unified("/etc/head", Some(old), Some(new)),
+            "--- /etc/head
++++ /etc/head
+@@ -2,8 +2,9 @@
+ b
+ c
+ d
+-e
++E
+ f
+ g
+ h
+ i
++j
+"
+        );
+    }
+
+    #[test]
+    fn distant_changes_get_their_own_hunks() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = "0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+
+        let diff = unified("f", Some(old), Some(new));
+
+        assert!(diff.contains("@@ -1,3 +1,4 @@\n+0\n 1\n 2\n 3\n"));
+        assert!(diff.contains("@@ -7,4 +8,3 @@\n 7\n 8\n 9\n-10\n"));
+    }
+
+    #[test]
+    fn created_removed_and_unchanged_files() {
+        assert_eq!(
+            unified("/etc/new", None, Some("x\n")),
+            "--- /dev/null\n+++ /etc/new\n@@ -0,0 +1,1 @@\n+x\n"
+        );
+        assert_eq!(
+            unified("/etc/old", Some("x\n"), None),
+            "--- /etc/old\n+++ /dev/null\n@@ -1,1 +0,0 @@\n-x\n"
+        );
+        assert_eq!(unified("/etc/same", Some("x\n"), Some("x\n")), "");
+    }
+}
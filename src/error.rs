@@ -0,0 +1,164 @@
+use std::error;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+use crate::backend::resolvconf::NotApplied;
+
+/// Why a command failed, each kind with its own exit status.
+///
+/// I/O errors are classified by their kind by `From<io::Error>`.
+#[derive(Debug)]
+pub enum Error {
+    /// The command line cannot be parsed.
+    Usage(String),
+    /// The configuration file cannot be parsed.
+    InvalidConfig(String),
+    /// Something only root may do, `what` saying what it was.
+    PermissionDenied { what: String },
+    /// A program the backend relies on is not installed.
+    BackendMissing {
+        program: String,
+        hint: Option<&'static str>,
+    },
+    /// The backend cannot configure what was asked for.
+    Unsupported(String),
+    /// A command run by the backend exited with a non-zero status.
+    CommandFailed {
+        command: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+    /// The resolver does not use the nameservers after activation.
+    VerificationFailed(NotApplied),
+    /// Another process is changing the configuration, `pid` if known.
+    Busy { pid: Option<u32> },
+    /// A file could not be written, replaced or removed.
+    WriteFailed { path: PathBuf, source: io::Error },
+    /// Any other I/O error.
+    Io(io::Error),
+    /// A transaction failed with `cause`, then rolling it back failed too.
+    RollbackFailed {
+        cause: Box<Error>,
+        rollback: Box<Error>,
+    },
+}
+
+impl Error {
+    /// The exit status of the process, as documented in the README.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Io(_) => 1,
+            Error::Usage(_) => 2,
+            Error::PermissionDenied { .. } => 3,
+            Error::BackendMissing { .. } => 4,
+            Error::Unsupported(_) => 5,
+            Error::CommandFailed { .. } => 6,
+            Error::VerificationFailed(_) => 7,
+            Error::Busy { .. } => 8,
+            Error::WriteFailed { .. } => 9,
+            Error::InvalidConfig(_) => 10,
+            Error::RollbackFailed { cause, .. } => cause.exit_code(),
+        }
+    }
+
+    /// Classifies the failure to write `path`.
+    pub fn write_failed(path: PathBuf, source: io::Error) -> Error {
+        match source.kind() {
+            ErrorKind::PermissionDenied => Error::PermissionDenied {
+                what: format!("cannot write {}", path.display()),
+            },
+            _ => Error::WriteFailed { path, source },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Usage(message) | Error::InvalidConfig(message) | Error::Unsupported(message) => {
+                write!(f, "{}", message)
+            }
+            Error::PermissionDenied { what } => {
+                write!(f, "{}: permission denied, try again with sudo", what)
+            }
+            Error::BackendMissing { program, hint } => {
+                write!(f, "`{}` is not installed", program)?;
+                match hint {
+                    Some(hint) => write!(f, ", {}", hint),
+                    None => Ok(()),
+                }
+            }
+            Error::CommandFailed {
+                command,
+                status,
+                stderr,
+            } => write!(f, "`{}` failed with {}: {}", command, status, stderr),
+            Error::VerificationFailed(not_applied) => write!(f, "{}", not_applied),
+            Error::Busy { pid: Some(pid) } => write!(
+                f,
+                "another cfg-adguard-dns (pid {}) is changing the configuration",
+                pid
+            ),
+            Error::Busy { pid: None } => {
+                write!(f, "another cfg-adguard-dns is changing the configuration")
+            }
+            Error::WriteFailed { path, source } => {
+                write!(f, "cannot write {}: {}", path.display(), source)
+            }
+            Error::Io(err) => write!(f, "{}", err),
+            Error::RollbackFailed { cause, rollback } => write!(f, "{}, then {}", cause, rollback),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::WriteFailed { source, .. } | Error::Io(source) => Some(source),
+            Error::RollbackFailed { cause, .. } => Some(cause),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        match err.kind() {
+            ErrorKind::PermissionDenied => Error::PermissionDenied {
+                what: err.to_string(),
+            },
+            ErrorKind::Unsupported => Error::Unsupported(err.to_string()),
+            _ => Error::Io(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_errors_are_kept_as_the_source() {
+        let err = Error::from(io::Error::other("disk full"));
+        assert_eq!(err.exit_code(), 1);
+        let source = error::Error::source(&err).unwrap();
+        assert_eq!(source.to_string(), "disk full");
+        assert!(source.is::<io::Error>());
+    }
+
+    #[test]
+    fn plain_io_errors_are_classified_by_kind() {
+        let denied = io::Error::from(ErrorKind::PermissionDenied);
+        assert_eq!(Error::from(denied).exit_code(), 3);
+        assert_eq!(
+            Error::write_failed(
+                PathBuf::from("/etc/resolv.conf"),
+                io::Error::from(ErrorKind::PermissionDenied)
+            )
+            .to_string(),
+            "cannot write /etc/resolv.conf: permission denied, try again with sudo"
+        );
+    }
+}
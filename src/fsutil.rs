@@ -0,0 +1,282 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions, Permissions};
+use std::io::{Error, ErrorKind, Write};
+use std::os::unix::fs::{self as unix_fs, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+
+/// Mode given to files that did not exist before, regardless of the umask.
+const NEW_FILE_MODE: u32 = 0o644;
+
+/// Linux gives up resolving a path after 40 symlinks, so do we.
+const MAX_SYMLINKS: usize = 40;
+
+/// Replaces the content of `path` so that readers see either the old or the
+/// new content, even if the process or the machine dies halfway.
+///
+/// The content goes to a temp file next to the target which is synced and
+/// renamed over it. Symlinks are followed so the file they point to is
+/// replaced, not the link. The mode and owner of an existing file are kept.
+pub fn write_atomic(path: &Path, contents: &str) -> Result<(), Error> {
+    replace_atomic(&resolve_symlinks(path)?, contents)
+}
+
+/// Like [`write_atomic`], except that a symlink at `path` is itself replaced
+/// by a regular file with the default mode.
+pub fn replace_atomic(path: &Path, contents: &str) -> Result<(), Error> {
+    replace_atomic_with_mode(path, contents, None)
+}
+
+/// Like [`replace_atomic`], the file getting `mode` rather than the mode it
+/// had. The content is never readable with another mode, even briefly.
+pub fn replace_atomic_with_mode(
+    path: &Path,
+    contents: &str,
+    mode: Option<u32>,
+) -> Result<(), Error> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let existing = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => None,
+        Ok(metadata) => Some(metadata),
+        Err(err) if err.kind() == ErrorKind::NotFound => None,
+        Err(err) => return Err(err),
+    };
+
+    let temp_path = temp_path(path)?;
+
+    let result = (|| {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&temp_path)?;
+        file.write_all(contents.as_bytes())?;
+
+        if let Some(metadata) = &existing {
+            let temp_metadata = file.metadata()?;
+            if (temp_metadata.uid(), temp_metadata.gid()) != (metadata.uid(), metadata.gid()) {
+                unix_fs::fchown(&file, Some(metadata.uid()), Some(metadata.gid()))?;
+            }
+        }
+        let permissions = match (mode, &existing) {
+            (Some(mode), _) => Permissions::from_mode(mode),
+            (None, Some(metadata)) => metadata.permissions(),
+            (None, None) => Permissions::from_mode(NEW_FILE_MODE),
+        };
+        file.set_permissions(permissions)?;
+
+        file.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        File::open(&dir)?.sync_all()
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Atomically makes `path` a symlink to `target`, whatever `path` was before.
+pub fn symlink_atomic(target: &Path, path: &Path) -> Result<(), Error> {
+    let temp_path = temp_path(path)?;
+    unix_fs::symlink(target, &temp_path)?;
+
+    let result = fs::rename(&temp_path, path);
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// A name for a temp file next to `path`, unique to this process.
+fn temp_path(path: &Path) -> Result<PathBuf, Error> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "path has no file name"))?;
+
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".cfg-adguard-dns.{}", process::id()));
+    Ok(path.with_file_name(temp_name))
+}
+
+/// Follows the symlinks in the absolute `path`, the last one possibly
+/// dangling.
+pub fn resolve_symlinks(path: &Path) -> Result<PathBuf, Error> {
+    resolve(path, true, read_link)
+}
+
+/// Where the symlink at `path` points, `None` meaning that it is not one.
+pub fn read_link(path: &Path) -> Result<Option<PathBuf>, Error> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => Ok(Some(fs::read_link(path)?)),
+        Ok(_) => Ok(None),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Follows the symlinks in the absolute `path` component by component, as
+/// a chroot would: absolute targets and `..` never go above `/`. The last
+/// component is only followed if `follow_last`. `read_link` tells where the
+/// symlink at a path, whose parent is resolved, points to.
+pub fn resolve<E: From<Error>>(
+    path: &Path,
+    follow_last: bool,
+    mut read_link: impl FnMut(&Path) -> Result<Option<PathBuf>, E>,
+) -> Result<PathBuf, E> {
+    let mut resolved = PathBuf::from("/");
+    let mut rest = components(path);
+    let mut links = 0;
+
+    while let Some(name) = rest.pop() {
+        match Path::new(&name).components().next() {
+            Some(Component::RootDir) => resolved = PathBuf::from("/"),
+            Some(Component::ParentDir) => {
+                resolved.pop();
+            }
+            Some(Component::Normal(_)) => {
+                let candidate = resolved.join(&name);
+                if rest.is_empty() && !follow_last {
+                    resolved = candidate;
+                    continue;
+                }
+                match read_link(&candidate)? {
+                    Some(_) if links == MAX_SYMLINKS => {
+                        return Err(Error::other(format!(
+                            "too many levels of symbolic links: {}",
+                            path.display()
+                        ))
+                        .into())
+                    }
+                    Some(target) => {
+                        links += 1;
+                        rest.extend(components(&target));
+                    }
+                    None => resolved = candidate,
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(resolved)
+}
+
+/// The components of `path`, last first, to be popped in order.
+fn components(path: &Path) -> Vec<OsString> {
+    path.components()
+        .rev()
+        .map(|component| component.as_os_str().to_os_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::os::unix::fs::symlink;
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn write_atomic_creates_and_replaces_files() -> Result<(), Error> {
+        let dir = TempDir::new("fsutil-write");
+        let path = dir.path().join("head");
+
+        write_atomic(&path, "first\n")?;
+        assert_eq!(fs::read_to_string(&path)?, "first\n");
+        assert_eq!(mode(&path), NEW_FILE_MODE);
+
+        fs::set_permissions(&path, Permissions::from_mode(0o640))?;
+        write_atomic(&path, "second\n")?;
+        assert_eq!(fs::read_to_string(&path)?, "second\n");
+        assert_eq!(mode(&path), 0o640);
+
+        assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn write_atomic_replaces_the_target_of_symlinks() -> Result<(), Error> {
+        let dir = TempDir::new("fsutil-symlink");
+        fs::create_dir(dir.path().join("real"))?;
+        fs::write(dir.path().join("real/head"), "old\n")?;
+        symlink("real/head", dir.path().join("head"))?;
+        symlink("missing", dir.path().join("dangling"))?;
+
+        write_atomic(&dir.path().join("head"), "new\n")?;
+        write_atomic(&dir.path().join("dangling"), "created\n")?;
+
+        assert!(fs::symlink_metadata(dir.path().join("head"))?
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(dir.path().join("real/head"))?, "new\n");
+        assert_eq!(fs::read_to_string(dir.path().join("missing"))?, "created\n");
+        Ok(())
+    }
+
+    #[test]
+    fn replace_atomic_and_symlink_atomic_replace_symlinks() -> Result<(), Error> {
+        let dir = TempDir::new("fsutil-replace");
+        fs::write(dir.path().join("stub"), "nameserver 127.0.0.53\n")?;
+        symlink("stub", dir.path().join("resolv.conf"))?;
+
+        replace_atomic(&dir.path().join("resolv.conf"), "nameserver 9.9.9.9\n")?;
+
+        let metadata = fs::symlink_metadata(dir.path().join("resolv.conf"))?;
+        assert!(metadata.file_type().is_file());
+        assert_eq!(metadata.permissions().mode() & 0o777, NEW_FILE_MODE);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("stub"))?,
+            "nameserver 127.0.0.53\n"
+        );
+
+        symlink_atomic(Path::new("stub"), &dir.path().join("resolv.conf"))?;
+
+        assert_eq!(
+            fs::read_link(dir.path().join("resolv.conf"))?,
+            Path::new("stub")
+        );
+        assert_eq!(fs::read_dir(dir.path())?.count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn symlink_loops_are_reported() {
+        let dir = TempDir::new("fsutil-loop");
+        symlink("b", dir.path().join("a")).unwrap();
+        symlink("a", dir.path().join("b")).unwrap();
+
+        assert!(write_atomic(&dir.path().join("a"), "").is_err());
+    }
+
+    #[test]
+    fn absolute_targets_stay_below_the_root() -> Result<(), Error> {
+        let links = |path: &Path| {
+            Ok::<_, Error>(match path.to_str() {
+                Some("/etc/resolv.conf") => Some(PathBuf::from("../run/resolv.conf")),
+                Some("/etc/run") => Some(PathBuf::from("/../../run")),
+                _ => None,
+            })
+        };
+
+        assert_eq!(
+            resolve(Path::new("/etc/resolv.conf"), true, links)?,
+            Path::new("/run/resolv.conf")
+        );
+        assert_eq!(
+            resolve(Path::new("/etc/resolv.conf"), false, links)?,
+            Path::new("/etc/resolv.conf")
+        );
+        assert_eq!(
+            resolve(Path::new("/etc/run/../etc/run/stub"), false, links)?,
+            Path::new("/run/stub")
+        );
+        Ok(())
+    }
+}
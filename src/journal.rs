@@ -0,0 +1,353 @@
+use std::fmt;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::fsutil;
+
+const ENTRIES_FILE: &str = "entries";
+
+/// The prior state of everything a transaction changes, kept on disk in its
+/// own directory until the transaction ends.
+///
+/// `entries` holds one tab-separated line per change, appended and synced
+/// before the change is made. File contents are saved next to it, numbered.
+/// A line cut short by a crash is ignored, its change was not made yet.
+/// The directories created to hold the journal are removed along with it
+/// when nothing else was put in them.
+pub struct Journal {
+    dir: PathBuf,
+    /// The ancestors of `dir` created for it, deepest first.
+    parents: Vec<PathBuf>,
+    entries: File,
+    recorded: Vec<PathBuf>,
+    saved: usize,
+}
+
+impl Journal {
+    /// Starts a journal in `dir`, failing if one is already there.
+    pub fn create(dir: &Path) -> Result<Journal, Error> {
+        let parents = missing_parents(dir);
+        if let Some(parent) = dir.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Only its owner may read it: it holds copies of files such as
+        // NetworkManager keyfiles, which contain secrets.
+        DirBuilder::new()
+            .mode(0o700)
+            .create(dir)
+            .map_err(|err| match err.kind() {
+                ErrorKind::AlreadyExists => Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("a change is already in progress, see {}", dir.display()),
+                ),
+                _ => err,
+            })?;
+
+        let entries = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .mode(0o600)
+            .open(dir.join(ENTRIES_FILE))?;
+        let mut journal = Journal {
+            dir: dir.to_path_buf(),
+            parents,
+            entries,
+            recorded: Vec::new(),
+            saved: 0,
+        };
+        journal.append(&["pid", &process::id().to_string()])?;
+        for parent in journal.parents.clone() {
+            journal.append(&["parent", &parent.to_string_lossy()])?;
+        }
+        Ok(journal)
+    }
+
+    /// Records the state of `path` before it is first changed, along with
+    /// the directories that will have to be created for it.
+    pub fn record(&mut self, path: &Path) -> Result<(), Error> {
+        if self.recorded.iter().any(|recorded| recorded == path) {
+            return Ok(());
+        }
+
+        for dir in missing_parents(path).iter().rev() {
+            self.append(&["dir", &dir.to_string_lossy()])?;
+        }
+
+        let path_str = path.to_string_lossy();
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let target = fs::read_link(path)?;
+                self.append(&["symlink", &path_str, &target.to_string_lossy()])?;
+            }
+            Ok(metadata) => {
+                let content = fs::read_to_string(path)?;
+                let saved = self.saved.to_string();
+                fsutil::replace_atomic_with_mode(&self.dir.join(&saved), &content, Some(0o600))?;
+                self.saved += 1;
+                let mode = format!("{:o}", metadata.permissions().mode() & 0o7777);
+                self.append(&["file", &path_str, &saved, &mode])?;
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                self.append(&["absent", &path_str])?;
+            }
+            Err(err) => return Err(err),
+        }
+
+        self.recorded.push(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Records a command to run again once the files are restored, so that
+    /// the services read them back.
+    pub fn record_reload(&mut self, program: &str, args: &[&str]) -> Result<(), Error> {
+        let mut fields = vec!["reload", program];
+        fields.extend(args);
+        self.append(&fields)
+    }
+
+    /// Forgets the prior state once the transaction succeeded.
+    pub fn commit(self) -> Result<(), Error> {
+        fs::remove_dir_all(&self.dir)?;
+        remove_empty(&self.parents);
+        Ok(())
+    }
+
+    fn append(&mut self, fields: &[&str]) -> Result<(), Error> {
+        if fields.iter().any(|field| field.contains(['\t', '\n'])) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot journal `{}`", fields.join(" ")),
+            ));
+        }
+        writeln!(self.entries, "{}", fields.join("\t"))?;
+        self.entries.sync_data()
+    }
+}
+
+/// The process which wrote the journal in `dir`, if there is one.
+pub fn owner(dir: &Path) -> Result<Option<u32>, Error> {
+    let content = match fs::read_to_string(dir.join(ENTRIES_FILE)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    Ok(content
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("pid\t"))
+        .and_then(|pid| pid.parse().ok()))
+}
+
+/// Puts back everything recorded in the journal in `dir`, latest change
+/// first, then runs each reload command once with `run`. The journal is
+/// removed unless a file could not be restored, so that the next attempt
+/// starts over. Returns whether there was a journal.
+pub fn roll_back<E: fmt::Display>(
+    dir: &Path,
+    mut run: impl FnMut(&str, &[&str]) -> Result<(), E>,
+) -> Result<bool, Error> {
+    let content = match fs::read_to_string(dir.join(ENTRIES_FILE)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            if dir.exists() {
+                fs::remove_dir_all(dir)?;
+                return Ok(true);
+            }
+            return Ok(false);
+        }
+        Err(err) => return Err(err),
+    };
+    // Without its newline, the last line may have been cut short.
+    let complete = match content.rfind('\n') {
+        Some(end) => &content[..end],
+        None => "",
+    };
+    let entries: Vec<Vec<&str>> = complete
+        .lines()
+        .map(|line| line.split('\t').collect())
+        .collect();
+
+    let mut errors = Vec::new();
+    for entry in entries.iter().rev() {
+        if let Err(err) = restore(dir, entry) {
+            errors.push(format!("{}: {}", entry.get(1).unwrap_or(&""), err));
+        }
+    }
+    let files_restored = errors.is_empty();
+
+    let mut reloads: Vec<&[&str]> = Vec::new();
+    for entry in &entries {
+        if let ["reload", command @ ..] = entry.as_slice() {
+            if !command.is_empty() && !reloads.contains(&command) {
+                reloads.push(command);
+            }
+        }
+    }
+    for command in reloads {
+        if let Err(err) = run(command[0], &command[1..]) {
+            errors.push(err.to_string());
+        }
+    }
+
+    if files_restored {
+        fs::remove_dir_all(dir)?;
+        let parents: Vec<PathBuf> = entries
+            .iter()
+            .filter_map(|entry| match entry.as_slice() {
+                ["parent", path] => Some(PathBuf::from(path)),
+                _ => None,
+            })
+            .collect();
+        remove_empty(&parents);
+    }
+    match errors.is_empty() {
+        true => Ok(true),
+        false => Err(Error::other(format!(
+            "rolling back failed: {}",
+            errors.join("; ")
+        ))),
+    }
+}
+
+/// The ancestors of `path` which do not exist, deepest first.
+fn missing_parents(path: &Path) -> Vec<PathBuf> {
+    let mut missing = Vec::new();
+    let mut parent = path.parent();
+    while let Some(dir) = parent.filter(|dir| !dir.as_os_str().is_empty() && !dir.exists()) {
+        missing.push(dir.to_path_buf());
+        parent = dir.parent();
+    }
+    missing
+}
+
+/// Removes the directories in order, leaving those something was put in.
+fn remove_empty(dirs: &[PathBuf]) {
+    for dir in dirs {
+        let _ = fs::remove_dir(dir);
+    }
+}
+
+fn restore(dir: &Path, entry: &[&str]) -> Result<(), Error> {
+    match entry {
+        ["file", path, saved, mode] => {
+            let content = fs::read_to_string(dir.join(saved))?;
+            let mode = u32::from_str_radix(mode, 8)
+                .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+            fsutil::replace_atomic_with_mode(Path::new(path), &content, Some(mode))
+        }
+        ["symlink", path, target] => fsutil::symlink_atomic(Path::new(target), Path::new(path)),
+        ["absent", path] => match fs::remove_file(path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        },
+        // Left in place if something else was put in it meanwhile.
+        ["dir", path] => {
+            let _ = fs::remove_dir(path);
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::fs::Permissions;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn roll_back_restores_the_recorded_state() -> Result<(), Error> {
+        let root = TempDir::new("journal-roll-back");
+        let dir = root.path().join("state/journal");
+        let file = root.path().join("head");
+        let link = root.path().join("resolv.conf");
+        let created = root.path().join("etc/dnsmasq.d/cfg-adguard-dns.conf");
+        fs::write(&file, "old\n")?;
+        fs::set_permissions(&file, Permissions::from_mode(0o640))?;
+        symlink("/run/resolvconf/resolv.conf", &link)?;
+
+        let mut journal = Journal::create(&dir)?;
+        for path in [&file, &link, &created, &file] {
+            journal.record(path)?;
+        }
+        journal.record_reload("resolvconf", &["-u"])?;
+        journal.record_reload("resolvconf", &["-u"])?;
+        assert!(Journal::create(&dir).is_err());
+        assert_eq!(fs::metadata(&dir)?.permissions().mode() & 0o777, 0o700);
+        assert_eq!(
+            fs::metadata(dir.join("0"))?.permissions().mode() & 0o777,
+            0o600
+        );
+        fsutil::write_atomic(&file, "new\n")?;
+        fsutil::replace_atomic(&link, "nameserver 9.9.9.9\n")?;
+        fs::create_dir_all(created.parent().unwrap())?;
+        fs::write(&created, "server=9.9.9.9\n")?;
+        // Dropped without a commit, as when the process dies.
+        drop(journal);
+        assert_eq!(owner(&dir)?, Some(process::id()));
+
+        let mut commands = Vec::new();
+        let rolled_back = roll_back(&dir, |program, args| {
+            commands.push(format!("{} {}", program, args.join(" ")));
+            Ok::<(), Error>(())
+        })?;
+
+        assert!(rolled_back);
+        assert_eq!(fs::read_to_string(&file)?, "old\n");
+        assert_eq!(fs::metadata(&file)?.permissions().mode() & 0o777, 0o640);
+        assert_eq!(
+            fs::read_link(&link)?,
+            Path::new("/run/resolvconf/resolv.conf")
+        );
+        assert!(!root.path().join("etc").exists());
+        assert_eq!(commands, ["resolvconf -u"]);
+        assert!(!root.path().join("state").exists());
+        assert!(!roll_back(&dir, |_, _| Ok::<(), Error>(()))?);
+        Ok(())
+    }
+
+    #[test]
+    fn cut_short_entry_is_ignored() -> Result<(), Error> {
+        let root = TempDir::new("journal-cut-short");
+        let dir = root.path().join("journal");
+        let file = root.path().join("head");
+        fs::write(&file, "old\n")?;
+
+        let mut journal = Journal::create(&dir)?;
+        journal.record(&file)?;
+        fs::write(&file, "new\n")?;
+        write!(
+            journal.entries,
+            "absent\t{}",
+            root.path().join("other").display()
+        )?;
+
+        roll_back(&dir, |_, _| Ok::<(), Error>(()))?;
+
+        assert_eq!(fs::read_to_string(&file)?, "old\n");
+        Ok(())
+    }
+
+    #[test]
+    fn committed_journal_is_removed() -> Result<(), Error> {
+        let root = TempDir::new("journal-commit");
+        let dir = root.path().join("state/journal");
+
+        Journal::create(&dir)?.commit()?;
+
+        assert!(!root.path().join("state").exists());
+        assert_eq!(owner(&dir)?, None);
+
+        let journal = Journal::create(&dir)?;
+        fs::create_dir(root.path().join("state/backups"))?;
+        journal.commit()?;
+
+        assert!(!dir.exists());
+        assert!(root.path().join("state/backups").is_dir());
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+//! Configures the system resolver to use a DNS provider such as AdGuard DNS.
+//!
+//! The machine is reached through [`System`], which every backend reads and
+//! writes files and runs commands with. [`activate`], [`deactivate`] and
+//! [`restore`] run as transactions: a failure rolls the machine back to its
+//! prior state, and [`System::recover`] rolls back a change interrupted by
+//! a crash. Nothing is printed: what the user should be told besides the
+//! outcome is taken from [`System::take_notes`], and [`proxy::run`] passes
+//! the queries it failed to forward to a function of the caller.
+//!
+//! ```no_run
+//! use cfg_adguard_dns::backend::detect;
+//! use cfg_adguard_dns::provider::{self, IpFamily, Profile};
+//! use cfg_adguard_dns::System;
+//!
+//! let system = System::new();
+//! let profile = Profile {
+//!     family: IpFamily::Both,
+//!     ..Profile::new(provider::find("quad9").unwrap())
+//! };
+//! system.recover()?;
+//! let backend = detect::detect(&system)?.backend;
+//! cfg_adguard_dns::activate(&system, &profile, backend)?;
+//! println!("{}", cfg_adguard_dns::status(&system)?);
+//! # Ok::<(), cfg_adguard_dns::Error>(())
+//! ```
+
+pub mod backend;
+pub mod backup;
+mod block;
+pub mod config;
+pub mod diff;
+pub mod error;
+mod fsutil;
+mod journal;
+pub mod provider;
+pub mod proxy;
+pub mod resolv_conf;
+pub mod status;
+pub mod system;
+#[cfg(test)]
+mod testutil;
+
+pub use error::Error;
+pub use system::System;
+
+use backend::{resolvconf, Backend};
+use backup::Backup;
+use provider::Profile;
+use status::Status;
+
+/// Activates `profile` with `backend`, refusing what the backend cannot
+/// configure. The client ID of the configuration file is used when an
+/// encrypted profile has none.
+pub fn activate(system: &System, profile: &Profile, backend: &dyn Backend) -> Result<(), Error> {
+    let mut profile = profile.clone();
+    if profile.encrypted && profile.client_id.is_none() {
+        profile.client_id = config::read(system)?.client_id_for(profile.provider);
+    }
+
+    backend::check_supported(backend, &profile)?;
+    system.transaction(|| backend.activate(system, &profile))?;
+    Ok(())
+}
+
+/// Deactivates `backend`, or every backend which has something configured,
+/// returning the backends deactivated.
+pub fn deactivate<'a>(
+    system: &System,
+    backend: Option<&'a dyn Backend>,
+) -> Result<Vec<&'a dyn Backend>, Error> {
+    let backends = match backend {
+        Some(backend) => vec![backend],
+        None => {
+            let mut configured = Vec::new();
+            for backend in backend::BACKENDS {
+                if !backend.configured(system)?.is_empty() {
+                    configured.push(*backend);
+                }
+            }
+            configured
+        }
+    };
+
+    system.transaction(|| {
+        for backend in &backends {
+            backend.deactivate(system)?;
+        }
+        Ok(())
+    })?;
+    Ok(backends)
+}
+
+/// Puts a backup back in place of the resolvconf head file, the most recent
+/// one when `id` is `None`.
+pub fn restore(system: &System, id: Option<&str>) -> Result<Backup, Error> {
+    system.transaction(|| resolvconf::restore(system, id))
+}
+
+/// Tells which provider is configured and in use, from the files only.
+pub fn status(system: &System) -> Result<Status, Error> {
+    status::check(system)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::direct::Direct;
+    use crate::testutil::{self, TempDir};
+
+    #[test]
+    fn activate_and_deactivate_every_configured_backend() -> Result<(), Error> {
+        let root = TempDir::new("lib-operations");
+        let system = System::with_root(root.path(), None);
+        system.write("/etc/resolv.conf", "nameserver 192.168.1.1\n")?;
+        let mut profile = Profile {
+            family: provider::IpFamily::V4,
+            encrypted: true,
+            ..testutil::profile("adguard")
+        };
+
+        assert_eq!(
+            activate(&system, &profile, &Direct)
+                .unwrap_err()
+                .exit_code(),
+            5
+        );
+        profile.encrypted = false;
+        activate(&system, &profile, &Direct)?;
+
+        assert_eq!(status(&system)?.active_provider(), Some(profile.provider));
+        let deactivated = deactivate(&system, None)?;
+        assert_eq!(deactivated.len(), 1);
+        assert_eq!(deactivated[0].name(), "resolv-conf");
+        assert_eq!(status(&system)?.active_provider(), None);
+        Ok(())
+    }
+}
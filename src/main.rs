@@ -1,167 +1,234 @@
 use std::env;
-use std::fs::File;
-use std::io::{Error, Write};
-use std::process::Command;
-
-const RESOLVCONF_HEAD_ENV_VAR: &str = "RESOLVCONF_HEAD_PATH";
-const RESOLVCONF_HEAD_DEFAULT_PATH: &str = "/etc/resolvconf/resolv.conf.d/head";
-
-const DEFAULT_TEMPLATE: &str = "
-# Dynamic resolv.conf(5) file for glibc resolver(3) generated by resolvconf(8)
-#     DO NOT EDIT THIS FILE BY HAND -- YOUR CHANGES WILL BE OVERWRITTEN
-# 127.0.0.53 is the systemd-resolved stub resolver.
-# run \"systemd-resolve --status\" to see details about the actual nameservers.
-";
-
-const DNS_SERVER_1_ADDR: &str = "94.140.14.14";
-const DNS_SERVER_2_ADDR: &str = "94.149.15.15";
-
-const ADGUARD_DNS_SERVER_CONFIG: &str = "
-# AdGuard DNS 
-# https://adguard-dns.com/en/public-dns.html
-nameserver 94.140.14.14
-nameserver 94.149.15.15
-";
-
-const HELP_MESSAGE: &str = "
-Usage: sudo cfg-adguard-dns [options...]
-
-        --activate      Activate AdGuard DNS server 
-        --deactivate    Deactivate AdGuard DNS server 
-        --status        Shows wether AdGuard DNS server is activated or not
-        --help          Display the current help message
-
-Disclaimer: Using this tool will restore the /etc/resolvconf/resolv.conf.d/head file to its default state.
-";
-
-fn main() -> Result<(), Error> {
-    let mut file = File::create(get_path())?;
-    let args: Vec<_> = env::args().collect();
-
-    match args.len() {
-        1 => println!("{}", HELP_MESSAGE),
-        _ => match &args[1][..] {
-            "--help" => println!("{}", HELP_MESSAGE),
-            "--activate" | "activate" => activate_adguard_dns(&mut file),
-            "--deactivate" | "deactivate" => deactivate_adguard_dns(&mut file),
-            "--status" | "status" => show_status(),
-            _ => eprintln!("Unknown argument. Try `cfg-adguard-dns --help` for more information"),
-        },
+use std::fs;
+use std::process;
+
+mod cli;
+
+use cfg_adguard_dns::backend::{detect, resolvconf, Backend};
+use cfg_adguard_dns::provider::{self, Profile};
+use cfg_adguard_dns::system::{Note, Pending, Preview};
+use cfg_adguard_dns::{backup, config, diff, proxy, Error, System};
+use cli::Command;
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+
+    if let Err(err) = run(&args) {
+        match err {
+            Error::Usage(_) => {
+                eprintln!("{}", err);
+                eprintln!("Try `cfg-adguard-dns --help` for more information");
+            }
+            _ => eprintln!("Error: {}", err),
+        }
+        process::exit(err.exit_code());
     }
-
-    Ok(())
 }
 
-fn get_path() -> String {
-    match env::var(RESOLVCONF_HEAD_ENV_VAR) {
-        Ok(value) => value,
-        Err(_) => String::from(RESOLVCONF_HEAD_DEFAULT_PATH),
-    }
-}
+fn run(args: &[String]) -> Result<(), Error> {
+    let (root, args) = cli::take_root(args)?;
+    let command = cli::parse(&args)?;
 
-fn activate_adguard_dns(file: &mut File) {
-    write_default_template_with_adguard_dns(file);
-    update_resolvconf();
+    let system = match &root {
+        Some(_) if matches!(command, Command::Proxy { .. }) => {
+            return Err(Error::Usage(String::from(
+                "`proxy` serves the running system, it does not take `--root`",
+            )))
+        }
+        Some(root) if !root.is_dir() => {
+            return Err(Error::Usage(format!(
+                "`--root {}` is not a directory",
+                root.display()
+            )))
+        }
+        Some(root) => System::at_root(root),
+        None => System::new(),
+    };
+    let system = match command.dry_run() {
+        true => system.dry_run(),
+        false => system,
+    };
+    let result = execute(&system, command);
+    // Whatever the operations did not print before their outcome.
+    print_notes(&system.take_notes());
+    result?;
 
-    println!("AdGuard DNS successfully activated");
+    if let Some(preview) = system.preview() {
+        print_preview(&preview);
+    }
+    Ok(())
 }
 
-fn deactivate_adguard_dns(file: &mut File) {
-    write_default_template(file);
-    update_resolvconf();
-
-    println!("AdGuard DNS successfully deactivated");
+fn execute(system: &System, command: Command) -> Result<(), Error> {
+    match command {
+        Command::Help => println!("{}", cli::HELP_MESSAGE),
+        Command::Activate {
+            profile, backend, ..
+        } => activate_dns(system, &profile, backend)?,
+        Command::Deactivate { backend, .. } => deactivate_dns(system, backend)?,
+        Command::Status => println!("{}", cfg_adguard_dns::status(system)?),
+        Command::Backend => println!("{}", detect::detect(system)?),
+        Command::Providers => list_providers(),
+        Command::Backups => list_backups(system)?,
+        Command::Restore { id, .. } => restore_backup(system, id.as_deref())?,
+        Command::Proxy {
+            provider,
+            transport,
+            client_id,
+            listen,
+        } => {
+            let config = config::read(system)?;
+            let provider = provider.unwrap_or_else(|| config.proxy_provider());
+            let client_id = match client_id {
+                Some(client_id) => Some(client_id),
+                None => config.client_id_for(provider),
+            };
+            let upstream = proxy::Upstream::new(provider, transport, client_id.as_deref());
+            println!(
+                "Forwarding DNS queries received on {} to {}",
+                listen, upstream
+            );
+            proxy::run(proxy::Proxy::new(upstream), listen, |err| {
+                eprintln!("{}", err)
+            })?
+        }
+    }
+    Ok(())
 }
 
-fn show_status() {
-    let output = std::process::Command::new("nslookup")
-        .arg("wikipedia.org")
-        .output()
-        .expect("failed to execute nslookup");
-
-    if let Ok(output) = String::from_utf8(output.stdout) {
-        if contains_server_1_or_2_config(output) {
-            println!("AdGuard DNS is activated")
-        } else {
-            println!("AdGuard DNS is deactivated")
+/// Prints what the library noted about the changes, warnings on stderr.
+fn print_notes(notes: &[Note]) {
+    for note in notes {
+        match note {
+            Note::Info(_) => println!("{}", note),
+            Note::Warning(_) => eprintln!("{}", note),
         }
-    } else {
-        eprintln!("nslookup is not installed or could not lookup wikipedia.org")
-    };
+    }
 }
 
-fn contains_server_1_or_2_config(output: String) -> bool {
-    output.contains(&DNS_SERVER_1_ADDR.to_string())
-        || output.contains(&DNS_SERVER_2_ADDR.to_string())
+/// Prints what a dry run would have done: a diff per file, then the
+/// commands in the order they would run.
+fn print_preview(preview: &Preview) {
+    for change in &preview.changes {
+        let path = change.path.to_string_lossy();
+        let before = change.before.as_deref();
+        match &change.after {
+            Pending::File(content) => print!("{}", diff::unified(&path, before, Some(content))),
+            Pending::Symlink(target) => {
+                println!("{} would become a symlink to {}", path, target.display())
+            }
+            Pending::Absent if before.is_none() => println!("{} would be removed", path),
+            Pending::Absent => print!("{}", diff::unified(&path, before, None)),
+        }
+    }
+    if !preview.commands.is_empty() {
+        println!("Would run:");
+        for command in &preview.commands {
+            println!("    {}", command);
+        }
+    }
+    println!("Dry run, nothing was changed");
 }
 
-fn write_default_template_with_adguard_dns(file: &mut File) {
-    let content = format!("{} {}", DEFAULT_TEMPLATE, ADGUARD_DNS_SERVER_CONFIG);
-    write!(file, "{}", content).expect("failed to write default template");
+/// Rolls back any change a previous run could not finish.
+fn recover(system: &System) -> Result<(), Error> {
+    if system.recover()? {
+        println!("Rolled back a change that was interrupted");
+    }
+    Ok(())
 }
 
-fn write_default_template(file: &mut File) {
-    write!(file, "{}", DEFAULT_TEMPLATE).expect("failed to write default template");
+fn list_providers() {
+    for provider in provider::PROVIDERS {
+        match provider.encrypted_only {
+            true => println!("{:<20}{} (encrypted only)", provider.name, provider.label),
+            false => println!("{:<20}{}", provider.name, provider.label),
+        }
+    }
 }
 
-fn update_resolvconf() {
-    let output = Command::new("resolvconf")
-        .arg("-u")
-        .output()
-        .expect("failed to update resolvconf");
-
-    if !output.stderr.is_empty() {
-        match std::str::from_utf8(&output.stderr) {
-            Ok(err) => panic!("{}", err),
-            Err(err) => panic!("Invalid UTF-8 sequence: {}", err),
-        }
+fn list_backups(system: &System) -> Result<(), Error> {
+    let backups = backup::list(&resolvconf::backups_dir(system)?)?;
+    if backups.is_empty() {
+        println!("No backup of the head file");
+    }
+    for backup in backups {
+        println!(
+            "{:<24}{} bytes",
+            backup.id,
+            fs::metadata(&backup.path)?.len()
+        );
     }
+    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
+fn restore_backup(system: &System, id: Option<&str>) -> Result<(), Error> {
+    recover(system)?;
+    let result = cfg_adguard_dns::restore(system, id);
+    print_notes(&system.take_notes());
+    let backup = result?;
+    if system.is_dry_run() {
+        return Ok(());
+    }
 
-    const RESOLVCONF_HEAD_ENV_VAR: &str = "RESOLVCONF_HEAD_PATH";
-    const RESOLVCONF_HEAD_DEFAULT_PATH: &str = "/etc/resolvconf/resolv.conf.d/head";
+    println!("Backup {} successfully restored", backup.id);
+    Ok(())
+}
 
-    #[test]
-    fn get_path_function_returns_the_resolvconf_head_env_var_value_if_it_is_set() {
-        if let Ok(value) = env::var(RESOLVCONF_HEAD_ENV_VAR) {
-            assert_eq!(get_path(), value)
-        } else {
-            assert_eq!(get_path(), RESOLVCONF_HEAD_DEFAULT_PATH)
+/// Activates `profile` with `backend`, or with the detected one.
+fn activate_dns(
+    system: &System,
+    profile: &Profile,
+    backend: Option<&dyn Backend>,
+) -> Result<(), Error> {
+    recover(system)?;
+    let backend = match backend {
+        Some(backend) => backend,
+        None => {
+            let detection = detect::detect(system)?;
+            println!("{}", detection);
+            detection.backend
         }
+    };
+    let result = cfg_adguard_dns::activate(system, profile, backend);
+    print_notes(&system.take_notes());
+    result?;
+    if system.is_dry_run() {
+        return Ok(());
     }
 
-    #[test]
-    fn activate_adguard_dns_test() -> std::io::Result<()> {
-        let mut file = File::create("test_file_with_adguard_dns").unwrap();
-
-        activate_adguard_dns(&mut file);
-
-        match fs::read_to_string("test_file_with_adguard_dns") {
-            Ok(content) => assert!(content.contains(ADGUARD_DNS_SERVER_CONFIG)),
-            Err(_) => panic!("test failed"),
-        };
-
-        fs::remove_file("test_file_with_adguard_dns")?;
-        Ok(())
+    println!(
+        "{} successfully activated with {}",
+        profile.provider.label,
+        backend.name()
+    );
+    if profile.via_proxy {
+        println!(
+            "Queries go through {}, make sure `cfg-adguard-dns proxy` is running",
+            proxy::DEFAULT_LISTEN_ADDR
+        );
     }
+    Ok(())
+}
 
-    #[test]
-    fn deactivate_adguard_dns_test() -> std::io::Result<()> {
-        let mut file = File::create("test_file_without_adguard_dns").unwrap();
-
-        deactivate_adguard_dns(&mut file);
-
-        match fs::read_to_string("test_file_without_adguard_dns") {
-            Ok(content) => assert!(!content.contains(ADGUARD_DNS_SERVER_CONFIG)),
-            Err(_) => panic!("test failed"),
-        };
+/// Deactivates `backend`, or every backend which has something configured.
+fn deactivate_dns(system: &System, backend: Option<&dyn Backend>) -> Result<(), Error> {
+    recover(system)?;
+    let result = cfg_adguard_dns::deactivate(system, backend);
+    print_notes(&system.take_notes());
+    let backends = result?;
+    if system.is_dry_run() {
+        return Ok(());
+    }
 
-        fs::remove_file("test_file_without_adguard_dns")?;
-        Ok(())
+    if backends.is_empty() {
+        println!("No DNS provider is configured");
     }
+    for backend in backends {
+        println!(
+            "DNS provider successfully deactivated from {}",
+            backend.name()
+        );
+    }
+    Ok(())
 }
@@ -0,0 +1,547 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::proxy;
+use crate::resolv_conf::MAX_NAMESERVERS;
+
+pub const DEFAULT_PROVIDER: &str = "adguard";
+/// The provider selected by a client ID alone.
+pub const CLIENT_ID_PROVIDER: &str = "adguard-private";
+
+#[derive(Debug, PartialEq)]
+pub struct Provider {
+    pub name: &'static str,
+    pub label: &'static str,
+    pub url: &'static str,
+    /// The name the DNS-over-TLS servers authenticate as, on port 853 of the
+    /// nameservers.
+    pub dot_host: &'static str,
+    /// The DNS-over-HTTPS endpoint.
+    pub doh_url: &'static str,
+    /// Whether the servers tell devices apart by a client ID, prepended to
+    /// the DNS-over-TLS host name and appended to the DNS-over-HTTPS path.
+    pub client_ids: bool,
+    /// Whether the servers only answer DNS-over-TLS and DNS-over-HTTPS, not
+    /// plain DNS on port 53.
+    pub encrypted_only: bool,
+    /// The IPv4 addresses first, then the IPv6 ones, each in order of preference.
+    pub nameservers: &'static [IpAddr],
+}
+
+/// Which of the provider addresses get written for the resolver.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum IpFamily {
+    #[default]
+    V4,
+    V6,
+    Both,
+}
+
+impl IpFamily {
+    pub fn parse(value: &str) -> Option<IpFamily> {
+        match value {
+            "v4" => Some(IpFamily::V4),
+            "v6" => Some(IpFamily::V6),
+            "both" => Some(IpFamily::Both),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for IpFamily {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IpFamily::V4 => write!(f, "v4"),
+            IpFamily::V6 => write!(f, "v6"),
+            IpFamily::Both => write!(f, "both"),
+        }
+    }
+}
+
+/// A provider together with how its nameservers should be configured.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub provider: &'static Provider,
+    pub family: IpFamily,
+    /// The network interfaces to configure, empty meaning every interface
+    /// the backend knows about. Only honored by backends with per-interface
+    /// settings.
+    pub interfaces: Vec<String>,
+    /// Whether to use DNS-over-TLS rather than plain DNS.
+    pub encrypted: bool,
+    /// Whether to point the resolver at `cfg-adguard-dns proxy` rather than
+    /// at the provider.
+    pub via_proxy: bool,
+    /// The device's client ID, for providers with `client_ids`.
+    pub client_id: Option<String>,
+}
+
+impl Profile {
+    /// Plain DNS to the IPv4 nameservers of `provider`, on every interface.
+    pub fn new(provider: &'static Provider) -> Profile {
+        Profile {
+            provider,
+            family: IpFamily::default(),
+            interfaces: Vec::new(),
+            encrypted: false,
+            via_proxy: false,
+            client_id: None,
+        }
+    }
+
+    pub fn nameservers(&self) -> Vec<IpAddr> {
+        match self.via_proxy {
+            true => vec![proxy::DEFAULT_LISTEN_ADDR.ip()],
+            false => self.provider.nameservers(self.family),
+        }
+    }
+
+    /// The name the DNS-over-TLS servers authenticate as for this device.
+    pub fn tls_host(&self) -> String {
+        self.provider.tls_host(self.client_id.as_deref())
+    }
+
+    /// Renders the resolv.conf lines pointing the resolver at the profile's
+    /// nameservers.
+    pub fn config(&self) -> String {
+        if !self.via_proxy {
+            return self.provider.config(self.family);
+        }
+        format!(
+            "# {} through `cfg-adguard-dns proxy`\n# {}\nnameserver {}\n",
+            self.provider.label,
+            self.provider.url,
+            proxy::DEFAULT_LISTEN_ADDR.ip()
+        )
+    }
+}
+
+const fn v4(a: u8, b: u8, c: u8, d: u8) -> IpAddr {
+    IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+}
+
+#[allow(clippy::too_many_arguments)]
+const fn v6(a: u16, b: u16, c: u16, d: u16, e: u16, f: u16, g: u16, h: u16) -> IpAddr {
+    IpAddr::V6(Ipv6Addr::new(a, b, c, d, e, f, g, h))
+}
+
+pub const PROVIDERS: &[Provider] = &[
+    Provider {
+        name: "adguard",
+        label: "AdGuard DNS",
+        url: "https://adguard-dns.com/en/public-dns.html",
+        dot_host: "dns.adguard-dns.com",
+        doh_url: "https://dns.adguard-dns.com/dns-query",
+        client_ids: false,
+        encrypted_only: false,
+        nameservers: &[
+            v4(94, 140, 14, 14),
+            v4(94, 140, 15, 15),
+            v6(0x2a10, 0x50c0, 0, 0, 0, 0, 0xad1, 0xff),
+            v6(0x2a10, 0x50c0, 0, 0, 0, 0, 0xad2, 0xff),
+        ],
+    },
+    Provider {
+        name: "adguard-family",
+        label: "AdGuard DNS Family Protection",
+        url: "https://adguard-dns.com/en/public-dns.html",
+        dot_host: "family.adguard-dns.com",
+        doh_url: "https://family.adguard-dns.com/dns-query",
+        client_ids: false,
+        encrypted_only: false,
+        nameservers: &[
+            v4(94, 140, 14, 15),
+            v4(94, 140, 15, 16),
+            v6(0x2a10, 0x50c0, 0, 0, 0, 0, 0xbad1, 0xff),
+            v6(0x2a10, 0x50c0, 0, 0, 0, 0, 0xbad2, 0xff),
+        ],
+    },
+    Provider {
+        name: "adguard-unfiltered",
+        label: "AdGuard DNS Non-filtering",
+        url: "https://adguard-dns.com/en/public-dns.html",
+        dot_host: "unfiltered.adguard-dns.com",
+        doh_url: "https://unfiltered.adguard-dns.com/dns-query",
+        client_ids: false,
+        encrypted_only: false,
+        nameservers: &[
+            v4(94, 140, 14, 140),
+            v4(94, 140, 14, 141),
+            v6(0x2a10, 0x50c0, 0, 0, 0, 0, 0x1, 0xff),
+            v6(0x2a10, 0x50c0, 0, 0, 0, 0, 0x2, 0xff),
+        ],
+    },
+    Provider {
+        name: "adguard-private",
+        label: "AdGuard DNS private server",
+        url: "https://adguard-dns.io/en/dashboard/",
+        dot_host: "d.adguard-dns.com",
+        doh_url: "https://d.adguard-dns.com/dns-query",
+        client_ids: true,
+        encrypted_only: false,
+        nameservers: &[
+            v4(94, 140, 14, 49),
+            v4(94, 140, 14, 59),
+            v6(0x2a10, 0x50c0, 0, 0, 0, 0, 0xded, 0xff),
+            v6(0x2a10, 0x50c0, 0, 0, 0, 0, 0xdad, 0xff),
+        ],
+    },
+    Provider {
+        name: "cloudflare",
+        label: "Cloudflare DNS",
+        url: "https://developers.cloudflare.com/1.1.1.1/ip-addresses/",
+        dot_host: "cloudflare-dns.com",
+        doh_url: "https://cloudflare-dns.com/dns-query",
+        client_ids: false,
+        encrypted_only: false,
+        nameservers: &[
+            v4(1, 1, 1, 1),
+            v4(1, 0, 0, 1),
+            v6(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111),
+            v6(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1001),
+        ],
+    },
+    Provider {
+        name: "cloudflare-malware",
+        label: "Cloudflare DNS Malware Blocking",
+        url: "https://developers.cloudflare.com/1.1.1.1/ip-addresses/",
+        dot_host: "security.cloudflare-dns.com",
+        doh_url: "https://security.cloudflare-dns.com/dns-query",
+        client_ids: false,
+        encrypted_only: false,
+        nameservers: &[
+            v4(1, 1, 1, 2),
+            v4(1, 0, 0, 2),
+            v6(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1112),
+            v6(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1002),
+        ],
+    },
+    Provider {
+        name: "cloudflare-family",
+        label: "Cloudflare DNS for Families",
+        url: "https://developers.cloudflare.com/1.1.1.1/ip-addresses/",
+        dot_host: "family.cloudflare-dns.com",
+        doh_url: "https://family.cloudflare-dns.com/dns-query",
+        client_ids: false,
+        encrypted_only: false,
+        nameservers: &[
+            v4(1, 1, 1, 3),
+            v4(1, 0, 0, 3),
+            v6(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1113),
+            v6(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1003),
+        ],
+    },
+    Provider {
+        name: "quad9",
+        label: "Quad9",
+        url: "https://www.quad9.net/service/service-addresses-and-features",
+        dot_host: "dns.quad9.net",
+        doh_url: "https://dns.quad9.net/dns-query",
+        client_ids: false,
+        encrypted_only: false,
+        nameservers: &[
+            v4(9, 9, 9, 9),
+            v4(149, 112, 112, 112),
+            v6(0x2620, 0xfe, 0, 0, 0, 0, 0, 0xfe),
+            v6(0x2620, 0xfe, 0, 0, 0, 0, 0, 0x9),
+        ],
+    },
+    Provider {
+        name: "mullvad",
+        label: "Mullvad DNS",
+        url: "https://mullvad.net/en/help/dns-over-https-and-dns-over-tls",
+        dot_host: "dns.mullvad.net",
+        doh_url: "https://dns.mullvad.net/dns-query",
+        client_ids: false,
+        encrypted_only: true,
+        nameservers: &[v4(194, 242, 2, 2), v6(0x2a07, 0xe340, 0, 0, 0, 0, 0, 0x2)],
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Provider> {
+    PROVIDERS.iter().find(|provider| provider.name == name)
+}
+
+/// Tells whether `id` can be used as a client ID: a host name label of
+/// lowercase letters, digits and dashes.
+pub fn is_valid_client_id(id: &str) -> bool {
+    (1..=63).contains(&id.len())
+        && !id.starts_with('-')
+        && !id.ends_with('-')
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+}
+
+/// Returns the provider owning the given nameserver address, if any.
+pub fn find_by_nameserver(addr: &IpAddr) -> Option<&'static Provider> {
+    PROVIDERS
+        .iter()
+        .find(|provider| provider.nameservers.contains(addr))
+}
+
+impl Provider {
+    /// The addresses of `family` the resolver should use, at most three as
+    /// glibc ignores the others. With both families the primary IPv4 and
+    /// IPv6 servers come first so either network can reach one of them.
+    pub fn nameservers(&self, family: IpFamily) -> Vec<IpAddr> {
+        let v4 = self.nameservers.iter().filter(|addr| addr.is_ipv4());
+        let v6 = self.nameservers.iter().filter(|addr| addr.is_ipv6());
+
+        let addrs: Vec<IpAddr> = match family {
+            IpFamily::V4 => v4.copied().collect(),
+            IpFamily::V6 => v6.copied().collect(),
+            IpFamily::Both => {
+                let (mut v4, mut v6) = (v4.copied(), v6.copied());
+                let mut addrs = Vec::new();
+                loop {
+                    let (a, b) = (v4.next(), v6.next());
+                    if a.is_none() && b.is_none() {
+                        break addrs;
+                    }
+                    addrs.extend(a);
+                    addrs.extend(b);
+                }
+            }
+        };
+        addrs.into_iter().take(MAX_NAMESERVERS).collect()
+    }
+
+    /// The name the DNS-over-TLS servers authenticate as, e.g.
+    /// `laptop.d.adguard-dns.com` for the client ID `laptop`.
+    pub fn tls_host(&self, client_id: Option<&str>) -> String {
+        match client_id {
+            Some(id) if self.client_ids => format!("{}.{}", id, self.dot_host),
+            _ => self.dot_host.to_string(),
+        }
+    }
+
+    /// The DNS-over-HTTPS endpoint, e.g.
+    /// `https://d.adguard-dns.com/dns-query/laptop` for the client ID `laptop`.
+    pub fn https_url(&self, client_id: Option<&str>) -> String {
+        match client_id {
+            Some(id) if self.client_ids => format!("{}/{}", self.doh_url, id),
+            _ => self.doh_url.to_string(),
+        }
+    }
+
+    /// Renders the resolv.conf lines pointing the resolver at this provider.
+    pub fn config(&self, family: IpFamily) -> String {
+        let mut config = format!("# {}\n# {}\n", self.label, self.url);
+        for addr in self.nameservers(family) {
+            config.push_str(&format!("nameserver {}\n", addr));
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provider_names_are_unique() {
+        for (i, provider) in PROVIDERS.iter().enumerate() {
+            assert!(PROVIDERS[i + 1..].iter().all(|p| p.name != provider.name))
+        }
+    }
+
+    /// Addresses as published on each provider's page, see `Provider::url`.
+    const PUBLISHED_NAMESERVERS: &[(&str, &[&str])] = &[
+        (
+            "adguard",
+            &[
+                "94.140.14.14",
+                "94.140.15.15",
+                "2a10:50c0::ad1:ff",
+                "2a10:50c0::ad2:ff",
+            ],
+        ),
+        (
+            "adguard-family",
+            &[
+                "94.140.14.15",
+                "94.140.15.16",
+                "2a10:50c0::bad1:ff",
+                "2a10:50c0::bad2:ff",
+            ],
+        ),
+        (
+            "adguard-unfiltered",
+            &[
+                "94.140.14.140",
+                "94.140.14.141",
+                "2a10:50c0::1:ff",
+                "2a10:50c0::2:ff",
+            ],
+        ),
+        (
+            "adguard-private",
+            &[
+                "94.140.14.49",
+                "94.140.14.59",
+                "2a10:50c0::ded:ff",
+                "2a10:50c0::dad:ff",
+            ],
+        ),
+        (
+            "cloudflare",
+            &[
+                "1.1.1.1",
+                "1.0.0.1",
+                "2606:4700:4700::1111",
+                "2606:4700:4700::1001",
+            ],
+        ),
+        (
+            "cloudflare-malware",
+            &[
+                "1.1.1.2",
+                "1.0.0.2",
+                "2606:4700:4700::1112",
+                "2606:4700:4700::1002",
+            ],
+        ),
+        (
+            "cloudflare-family",
+            &[
+                "1.1.1.3",
+                "1.0.0.3",
+                "2606:4700:4700::1113",
+                "2606:4700:4700::1003",
+            ],
+        ),
+        (
+            "quad9",
+            &["9.9.9.9", "149.112.112.112", "2620:fe::fe", "2620:fe::9"],
+        ),
+        ("mullvad", &["194.242.2.2", "2a07:e340::2"]),
+    ];
+
+    #[test]
+    fn every_provider_matches_its_published_nameservers() {
+        assert_eq!(PROVIDERS.len(), PUBLISHED_NAMESERVERS.len());
+
+        for (name, published) in PUBLISHED_NAMESERVERS {
+            let provider = find(name).unwrap_or_else(|| panic!("{} is not registered", name));
+            let published: Vec<IpAddr> =
+                published.iter().map(|addr| addr.parse().unwrap()).collect();
+            assert_eq!(provider.nameservers, &published[..], "{}", name);
+        }
+    }
+
+    #[test]
+    fn nameservers_are_public_unicast_addresses() {
+        for provider in PROVIDERS {
+            assert!(!provider.nameservers.is_empty(), "{}", provider.name);
+            for addr in provider.nameservers {
+                let valid = match addr {
+                    IpAddr::V4(addr) => {
+                        !(addr.is_private()
+                            || addr.is_loopback()
+                            || addr.is_link_local()
+                            || addr.is_unspecified()
+                            || addr.is_broadcast()
+                            || addr.is_multicast()
+                            || addr.is_documentation())
+                    }
+                    IpAddr::V6(addr) => {
+                        !(addr.is_loopback() || addr.is_unspecified() || addr.is_multicast())
+                    }
+                };
+                assert!(valid, "{} of {}", addr, provider.name);
+            }
+        }
+    }
+
+    #[test]
+    fn nameservers_belong_to_a_single_provider() {
+        for provider in PROVIDERS {
+            for addr in provider.nameservers {
+                assert_eq!(find_by_nameserver(addr), Some(provider));
+            }
+        }
+    }
+
+    #[test]
+    fn nameservers_are_filtered_by_family() {
+        let provider = find("adguard").unwrap();
+        let addrs = |family| -> Vec<String> {
+            provider
+                .nameservers(family)
+                .iter()
+                .map(IpAddr::to_string)
+                .collect()
+        };
+
+        assert_eq!(addrs(IpFamily::V4), ["94.140.14.14", "94.140.15.15"]);
+        assert_eq!(
+            addrs(IpFamily::V6),
+            ["2a10:50c0::ad1:ff", "2a10:50c0::ad2:ff"]
+        );
+        assert_eq!(
+            addrs(IpFamily::Both),
+            ["94.140.14.14", "2a10:50c0::ad1:ff", "94.140.15.15"]
+        );
+    }
+
+    #[test]
+    fn ipv4_addresses_come_before_ipv6_ones() {
+        for provider in PROVIDERS {
+            let first_v6 = provider.nameservers.iter().position(IpAddr::is_ipv6);
+            let last_v4 = provider.nameservers.iter().rposition(IpAddr::is_ipv4);
+            assert!(first_v6 > last_v4, "{}", provider.name);
+        }
+    }
+
+    #[test]
+    fn encrypted_endpoints_are_served_by_the_dot_host() {
+        for provider in PROVIDERS {
+            assert!(
+                provider
+                    .doh_url
+                    .starts_with(&format!("https://{}/", provider.dot_host)),
+                "{}",
+                provider.name
+            );
+        }
+    }
+
+    #[test]
+    fn client_id_is_embedded_in_the_encrypted_endpoints() {
+        let private = find("adguard-private").unwrap();
+        assert_eq!(
+            private.tls_host(Some("laptop-1")),
+            "laptop-1.d.adguard-dns.com"
+        );
+        assert_eq!(
+            private.https_url(Some("laptop-1")),
+            "https://d.adguard-dns.com/dns-query/laptop-1"
+        );
+        assert_eq!(private.tls_host(None), "d.adguard-dns.com");
+
+        let public = find("adguard").unwrap();
+        assert_eq!(public.tls_host(Some("laptop-1")), "dns.adguard-dns.com");
+
+        assert!(is_valid_client_id("laptop-1"));
+        for id in ["", "Laptop", "laptop.home", "-laptop", &"a".repeat(64)] {
+            assert!(!is_valid_client_id(id), "{}", id);
+        }
+    }
+
+    #[test]
+    fn default_provider_is_registered() {
+        assert!(find(DEFAULT_PROVIDER).is_some());
+        assert!(find(CLIENT_ID_PROVIDER).is_some_and(|provider| provider.client_ids));
+    }
+
+    #[test]
+    fn config_lists_every_nameserver_of_the_provider() {
+        let provider = find("quad9").unwrap();
+        let config = provider.config(IpFamily::V4);
+
+        assert!(config.contains("# Quad9"));
+        assert!(config.contains("nameserver 9.9.9.9\n"));
+        assert!(config.contains("nameserver 149.112.112.112\n"));
+    }
+}
@@ -0,0 +1,842 @@
+use std::fmt;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::provider::{IpFamily, Provider};
+
+/// Where the proxy listens unless told otherwise, and what `--via-proxy`
+/// writes as the nameserver. glibc only queries port 53.
+pub const DEFAULT_LISTEN_ADDR: SocketAddr =
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 153)), 53);
+
+const HEADER_LEN: usize = 12;
+/// What a UDP answer may weigh when the query does not advertise more.
+const MIN_UDP_PAYLOAD: usize = 512;
+const OPT_TYPE: u16 = 41;
+/// How long a query may take on one upstream address.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+/// DNS-over-TLS connections kept open for the next queries.
+const MAX_IDLE_TLS: usize = 4;
+/// Threads answering UDP queries, and the queries waiting for one.
+const UDP_WORKERS: usize = 16;
+const UDP_QUEUE_LEN: usize = 256;
+
+/// How queries reach the provider.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Transport {
+    /// DNS-over-TLS, through a long-lived `openssl s_client`.
+    #[default]
+    Tls,
+    /// DNS-over-HTTPS, through one `curl` per query.
+    Https,
+}
+
+impl Transport {
+    pub fn parse(value: &str) -> Option<Transport> {
+        match value {
+            "tls" => Some(Transport::Tls),
+            "https" => Some(Transport::Https),
+            _ => None,
+        }
+    }
+}
+
+/// An encrypted DNS server of a provider, reachable at several addresses
+/// tried in turn.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Upstream {
+    Tls {
+        addrs: Vec<SocketAddr>,
+        host: String,
+    },
+    /// `addrs` are where the URL host is reached, so that the proxy does not
+    /// need a resolver to find its own upstream.
+    Https { url: String, addrs: Vec<IpAddr> },
+}
+
+impl Upstream {
+    /// The provider's servers, the IPv4 ones first, in order of preference.
+    pub fn new(provider: &Provider, transport: Transport, client_id: Option<&str>) -> Upstream {
+        let mut addrs = provider.nameservers(IpFamily::V4);
+        addrs.extend(provider.nameservers(IpFamily::V6));
+        match transport {
+            Transport::Tls => Upstream::Tls {
+                addrs: addrs
+                    .into_iter()
+                    .map(|addr| SocketAddr::new(addr, 853))
+                    .collect(),
+                host: provider.tls_host(client_id),
+            },
+            Transport::Https => Upstream::Https {
+                url: provider.https_url(client_id),
+                addrs,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list = |addrs: Vec<String>| addrs.join(", ");
+        match self {
+            Upstream::Tls { addrs, host } => write!(
+                f,
+                "tls://{} ({})",
+                host,
+                list(addrs.iter().map(SocketAddr::to_string).collect())
+            ),
+            Upstream::Https { url, addrs } => write!(
+                f,
+                "{} ({})",
+                url,
+                list(addrs.iter().map(IpAddr::to_string).collect())
+            ),
+        }
+    }
+}
+
+/// Forwards plain DNS messages to an encrypted upstream.
+pub struct Proxy {
+    upstream: Upstream,
+    /// Certificates to authenticate the upstream with instead of the
+    /// system ones.
+    ca_file: Option<PathBuf>,
+    /// How long a query may take on one upstream address, connection
+    /// included, before the next address is tried.
+    timeout: Duration,
+    /// The index of the upstream address which answered last, tried first.
+    preferred: AtomicUsize,
+    /// DNS-over-TLS connections not in use. The lock is only held to take
+    /// or put back one, never during a query.
+    idle_tls: Mutex<Vec<TlsStream>>,
+}
+
+/// A DNS-over-TLS connection: `openssl s_client` moves the bytes written to
+/// its stdin through TLS and writes what comes back to its stdout, where a
+/// thread of its own reads the responses so that waiting for them can time
+/// out.
+struct TlsStream {
+    child: Child,
+    stdin: ChildStdin,
+    responses: Receiver<Result<Vec<u8>, Error>>,
+}
+
+impl TlsStream {
+    fn exchange(&mut self, query: &[u8], timeout: Duration) -> Result<Vec<u8>, Error> {
+        write_message(&mut self.stdin, query)?;
+        match self.responses.recv_timeout(timeout) {
+            Ok(response) => response,
+            Err(RecvTimeoutError::Timeout) => Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("no response within {:?}", timeout),
+            )),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::from(ErrorKind::UnexpectedEof)),
+        }
+    }
+}
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        // The reader thread ends with the output of the process.
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Proxy {
+    pub fn new(upstream: Upstream) -> Proxy {
+        Proxy {
+            upstream,
+            ca_file: None,
+            timeout: UPSTREAM_TIMEOUT,
+            preferred: AtomicUsize::new(0),
+            idle_tls: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Sends `query` upstream and returns the response.
+    pub fn resolve(&self, query: &[u8]) -> Result<Vec<u8>, Error> {
+        if query.len() < HEADER_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, "DNS message too short"));
+        }
+
+        let mut response = match &self.upstream {
+            Upstream::Tls { addrs, host } => self.resolve_tls(query, addrs, host)?,
+            Upstream::Https { url, addrs } => {
+                self.failover(addrs, |addr| self.resolve_https(query, url, *addr))?
+            }
+        };
+        if response.len() < HEADER_LEN || response.len() > u16::MAX as usize {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "invalid response of {} bytes from {}",
+                    response.len(),
+                    self.upstream
+                ),
+            ));
+        }
+        response[..2].copy_from_slice(&query[..2]);
+        Ok(response)
+    }
+
+    /// Runs `f` on each address in turn, from the preferred one, until one
+    /// succeeds. The one which did becomes preferred.
+    fn failover<A, T>(
+        &self,
+        addrs: &[A],
+        mut f: impl FnMut(&A) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let first = self.preferred.load(Ordering::Relaxed);
+        let mut errors = Vec::new();
+        for i in (0..addrs.len()).map(|i| (first + i) % addrs.len()) {
+            match f(&addrs[i]) {
+                Ok(value) => {
+                    self.preferred.store(i, Ordering::Relaxed);
+                    return Ok(value);
+                }
+                Err(err) => errors.push(err),
+            }
+        }
+        let kind = errors.last().map_or(ErrorKind::NotFound, Error::kind);
+        let errors: Vec<_> = errors.iter().map(Error::to_string).collect();
+        Err(Error::new(
+            kind,
+            format!("query to {} failed: {}", self.upstream, errors.join("; ")),
+        ))
+    }
+
+    fn resolve_tls(
+        &self,
+        query: &[u8],
+        addrs: &[SocketAddr],
+        host: &str,
+    ) -> Result<Vec<u8>, Error> {
+        // The server may have closed an idle connection, which then only
+        // costs a new one.
+        let idle = self
+            .idle_tls
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .pop();
+        if let Some(mut stream) = idle {
+            if let Ok(response) = stream.exchange(query, self.timeout) {
+                self.keep_tls(stream);
+                return Ok(response);
+            }
+        }
+
+        self.failover(addrs, |addr| {
+            let mut stream = self.connect_tls(*addr, host)?;
+            let response = stream
+                .exchange(query, self.timeout)
+                .map_err(|err| Error::new(err.kind(), format!("{}: {}", addr, err)))?;
+            self.keep_tls(stream);
+            Ok(response)
+        })
+    }
+
+    fn keep_tls(&self, stream: TlsStream) {
+        let mut idle = self.idle_tls.lock().unwrap_or_else(|err| err.into_inner());
+        if idle.len() < MAX_IDLE_TLS {
+            idle.push(stream);
+        }
+    }
+
+    fn connect_tls(&self, addr: SocketAddr, host: &str) -> Result<TlsStream, Error> {
+        let mut command = Command::new("openssl");
+        command
+            .args(["s_client", "-quiet", "-connect", &addr.to_string()])
+            .args(["-servername", host, "-verify_hostname", host])
+            .arg("-verify_return_error");
+        if let Some(ca_file) = &self.ca_file {
+            command.arg("-CAfile").arg(ca_file);
+        }
+
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| not_installed("openssl", err))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+
+        let (sender, responses) = mpsc::channel();
+        thread::spawn(move || loop {
+            let response = read_message(&mut stdout);
+            let failed = response.is_err();
+            if sender.send(response).is_err() || failed {
+                break;
+            }
+        });
+        Ok(TlsStream {
+            child,
+            stdin,
+            responses,
+        })
+    }
+
+    fn resolve_https(&self, query: &[u8], url: &str, addr: IpAddr) -> Result<Vec<u8>, Error> {
+        let (host, port) = host_and_port(url).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, format!("invalid DoH URL {}", url))
+        })?;
+        let resolve = match addr {
+            IpAddr::V4(addr) => format!("{}:{}:{}", host, port, addr),
+            IpAddr::V6(addr) => format!("{}:{}:[{}]", host, port, addr),
+        };
+
+        let mut command = Command::new("curl");
+        command
+            .args(["--silent", "--show-error", "--fail", "--max-time"])
+            .arg(self.timeout.as_secs_f64().to_string())
+            .args(["--resolve", &resolve])
+            .args(["--header", "content-type: application/dns-message"])
+            .args(["--header", "accept: application/dns-message"])
+            .args(["--data-binary", "@-", url]);
+        if let Some(ca_file) = &self.ca_file {
+            command.arg("--cacert").arg(ca_file);
+        }
+
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| not_installed("curl", err))?;
+
+        // RFC 8484 asks for a zero ID so that responses can be cached.
+        let mut body = query.to_vec();
+        body[..2].copy_from_slice(&[0, 0]);
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(&body)?;
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(Error::other(format!(
+                "{}: {}",
+                addr,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(output.stdout)
+    }
+}
+
+fn not_installed(program: &str, err: Error) -> Error {
+    match err.kind() {
+        ErrorKind::NotFound => Error::new(
+            ErrorKind::NotFound,
+            format!("`{}` is not installed", program),
+        ),
+        _ => err,
+    }
+}
+
+/// Writes a message prefixed with its length, as DNS over TCP and TLS do.
+fn write_message(writer: &mut impl Write, message: &[u8]) -> Result<(), Error> {
+    let len = u16::try_from(message.len())
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "DNS message too long"))?;
+    let mut framed = len.to_be_bytes().to_vec();
+    framed.extend_from_slice(message);
+    writer.write_all(&framed)?;
+    writer.flush()
+}
+
+fn read_message(reader: &mut impl Read) -> Result<Vec<u8>, Error> {
+    let mut len = [0; 2];
+    reader.read_exact(&mut len)?;
+    let mut message = vec![0; u16::from_be_bytes(len) as usize];
+    reader.read_exact(&mut message)?;
+    Ok(message)
+}
+
+/// Splits the authority of an `https://` URL, the port defaulting to 443.
+fn host_and_port(url: &str) -> Option<(&str, u16)> {
+    let authority = url.strip_prefix("https://")?.split('/').next()?;
+    match authority.rsplit_once(':') {
+        Some((host, port)) if !host.ends_with(':') => Some((host, port.parse().ok()?)),
+        _ => Some((authority, 443)),
+    }
+}
+
+/// Listens on `addr` over UDP and TCP until the process is killed. The
+/// queries which cannot be forwarded are answered with SERVFAIL and their
+/// error passed to `report`.
+pub fn run(proxy: Proxy, addr: SocketAddr, report: fn(&Error)) -> Result<(), Error> {
+    let proxy = Arc::new(proxy);
+    let socket = UdpSocket::bind(addr)?;
+    let listener = TcpListener::bind(addr)?;
+
+    let tcp_proxy = Arc::clone(&proxy);
+    thread::spawn(move || serve_tcp(&tcp_proxy, listener, report));
+    serve_udp(&proxy, socket, report)
+}
+
+/// Answers UDP queries with a fixed number of workers. Queries arriving
+/// while the queue is full are dropped, the clients retry them.
+fn serve_udp(proxy: &Arc<Proxy>, socket: UdpSocket, report: fn(&Error)) -> Result<(), Error> {
+    let socket = Arc::new(socket);
+    let (sender, queries) = mpsc::sync_channel::<(Vec<u8>, SocketAddr)>(UDP_QUEUE_LEN);
+    let queries = Arc::new(Mutex::new(queries));
+
+    for _ in 0..UDP_WORKERS {
+        let (proxy, socket, queries) =
+            (Arc::clone(proxy), Arc::clone(&socket), Arc::clone(&queries));
+        thread::spawn(move || loop {
+            let next = queries.lock().unwrap_or_else(|err| err.into_inner()).recv();
+            let Ok((query, peer)) = next else {
+                break;
+            };
+            let mut response = answer(&proxy, &query, report);
+            if response.len() > udp_payload_size(&query) {
+                response = truncate(&response);
+            }
+            let _ = socket.send_to(&response, peer);
+        });
+    }
+
+    let mut buf = [0; 65535];
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf)?;
+        let _ = sender.try_send((buf[..len].to_vec(), peer));
+    }
+}
+
+fn serve_tcp(proxy: &Arc<Proxy>, listener: TcpListener, report: fn(&Error)) {
+    for stream in listener.incoming().flatten() {
+        let proxy = Arc::clone(proxy);
+        thread::spawn(move || handle_tcp(&proxy, stream, report));
+    }
+}
+
+fn handle_tcp(proxy: &Proxy, mut stream: TcpStream, report: fn(&Error)) {
+    while let Ok(query) = read_message(&mut stream) {
+        if write_message(&mut stream, &answer(proxy, &query, report)).is_err() {
+            break;
+        }
+    }
+}
+
+/// The upstream response, or SERVFAIL when there is none.
+fn answer(proxy: &Proxy, query: &[u8], report: fn(&Error)) -> Vec<u8> {
+    match proxy.resolve(query) {
+        Ok(response) => response,
+        Err(err) => {
+            report(&err);
+            servfail(query)
+        }
+    }
+}
+
+fn servfail(query: &[u8]) -> Vec<u8> {
+    let end = question_end(query).unwrap_or(HEADER_LEN.min(query.len()));
+    let mut response = query[..end].to_vec();
+    if response.len() >= HEADER_LEN {
+        response[2] |= 0x80;
+        response[3] = (response[3] & 0xf0) | 2;
+        response[6..HEADER_LEN].fill(0);
+    }
+    response
+}
+
+/// Cuts a response down to its header and question with the TC bit set,
+/// telling the client to retry over TCP.
+fn truncate(response: &[u8]) -> Vec<u8> {
+    let end = question_end(response).unwrap_or(HEADER_LEN);
+    let mut truncated = response[..end].to_vec();
+    truncated[2] |= 0x02;
+    truncated[6..HEADER_LEN].fill(0);
+    truncated
+}
+
+/// The largest UDP response the client accepts: what its EDNS OPT record
+/// advertises, 512 bytes otherwise.
+fn udp_payload_size(query: &[u8]) -> usize {
+    let size = (|| {
+        let count = |i: usize| Some(u16::from_be_bytes([*query.get(i)?, *query.get(i + 1)?]));
+        let mut pos = question_end(query)?;
+        let records = count(6)? + count(8)?;
+        for _ in 0..records {
+            pos = skip_record(query, pos)?;
+        }
+        for _ in 0..count(10)? {
+            let fields = skip_name(query, pos)?;
+            if u16::from_be_bytes([*query.get(fields)?, *query.get(fields + 1)?]) == OPT_TYPE {
+                return Some(
+                    u16::from_be_bytes([*query.get(fields + 2)?, *query.get(fields + 3)?]) as usize,
+                );
+            }
+            pos = skip_record(query, pos)?;
+        }
+        None
+    })();
+    size.unwrap_or(MIN_UDP_PAYLOAD).max(MIN_UDP_PAYLOAD)
+}
+
+/// The offset following the question section.
+fn question_end(message: &[u8]) -> Option<usize> {
+    let count = u16::from_be_bytes([*message.get(4)?, *message.get(5)?]);
+    let mut pos = HEADER_LEN;
+    for _ in 0..count {
+        pos = skip_name(message, pos)? + 4;
+    }
+    (pos <= message.len()).then_some(pos)
+}
+
+fn skip_record(message: &[u8], pos: usize) -> Option<usize> {
+    let fields = skip_name(message, pos)?;
+    let rdlength = u16::from_be_bytes([*message.get(fields + 8)?, *message.get(fields + 9)?]);
+    Some(fields + 10 + rdlength as usize)
+}
+
+fn skip_name(message: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(pos)?;
+        match len {
+            0 => return Some(pos + 1),
+            len if len & 0xc0 == 0xc0 => return Some(pos + 2),
+            len => pos += 1 + len as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider;
+    use crate::testutil::TempDir;
+    use std::path::Path;
+    use std::process::ChildStdout;
+
+    /// A query for `example.com. A` with ID 0x1234, and an EDNS OPT record
+    /// advertising `payload` bytes if given.
+    fn query(payload: Option<u16>) -> Vec<u8> {
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        if let Some(payload) = payload {
+            query[11] = 1;
+            query.extend_from_slice(&[0, 0, 41]);
+            query.extend_from_slice(&payload.to_be_bytes());
+            query.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        }
+        query
+    }
+
+    /// The response to `query` the test servers give: the query itself
+    /// flagged as a response, followed by `extra` bytes of padding.
+    fn response(query: &[u8], extra: usize) -> Vec<u8> {
+        let mut response = query.to_vec();
+        response[2] |= 0x80;
+        response.resize(query.len() + extra, 0);
+        response
+    }
+
+    #[test]
+    fn message_sections_are_walked() {
+        let plain = query(None);
+        assert_eq!(question_end(&plain), Some(plain.len()));
+        assert_eq!(udp_payload_size(&plain), 512);
+        assert_eq!(udp_payload_size(&query(Some(1232))), 1232);
+        assert_eq!(udp_payload_size(&query(Some(100))), 512);
+        assert_eq!(udp_payload_size(&plain[..5]), 512);
+    }
+
+    #[test]
+    fn oversized_answers_are_truncated_and_failures_are_servfail() {
+        let plain = query(None);
+
+        let truncated = truncate(&response(&plain, 600));
+        assert_eq!(truncated.len(), plain.len());
+        assert_eq!(truncated[2], 0x83);
+        assert_eq!(&truncated[6..12], &[0; 6]);
+
+        let failed = servfail(&query(Some(1232)));
+        assert_eq!(failed.len(), plain.len());
+        assert_eq!(failed[..4], [0x12, 0x34, 0x81, 0x02]);
+    }
+
+    #[test]
+    fn failures_are_reported_to_the_caller() {
+        static REPORTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        let adguard = provider::find("adguard").unwrap();
+        let proxy = Proxy::new(Upstream::new(adguard, Transport::Tls, None));
+
+        let response = answer(&proxy, &[0x12, 0x34], |err| {
+            REPORTED.lock().unwrap().push(err.to_string())
+        });
+
+        assert_eq!(response, [0x12, 0x34]);
+        assert_eq!(*REPORTED.lock().unwrap(), ["DNS message too short"]);
+    }
+
+    #[test]
+    fn upstream_lists_the_ipv4_nameservers_first() {
+        let adguard = provider::find("adguard").unwrap();
+
+        let Upstream::Tls { addrs, host } = Upstream::new(adguard, Transport::Tls, None) else {
+            panic!("not a DNS-over-TLS upstream");
+        };
+        assert_eq!(host, "dns.adguard-dns.com");
+        assert_eq!(addrs.len(), 4);
+        assert_eq!(addrs[0], "94.140.14.14:853".parse().unwrap());
+        assert_eq!(addrs[1], "94.140.15.15:853".parse().unwrap());
+        assert!(addrs[2].is_ipv6());
+
+        let private = provider::find("adguard-private").unwrap();
+        let Upstream::Https { url, addrs } =
+            Upstream::new(private, Transport::Https, Some("laptop"))
+        else {
+            panic!("not a DNS-over-HTTPS upstream");
+        };
+        assert_eq!(url, "https://d.adguard-dns.com/dns-query/laptop");
+        assert_eq!(
+            addrs[..2],
+            [
+                "94.140.14.49".parse::<IpAddr>().unwrap(),
+                "94.140.14.59".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            host_and_port("https://dns.adguard-dns.com/dns-query"),
+            Some(("dns.adguard-dns.com", 443))
+        );
+        assert_eq!(
+            host_and_port("https://localhost:8443/dns-query"),
+            Some(("localhost", 8443))
+        );
+    }
+
+    #[test]
+    fn messages_too_long_for_their_length_prefix_are_refused() {
+        let mut framed = Vec::new();
+        write_message(&mut framed, &[7; 3]).unwrap();
+        assert_eq!(framed, [0, 3, 7, 7, 7]);
+
+        let err = write_message(&mut Vec::new(), &vec![0; 65536]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    /// A TLS server on loopback for `localhost`, whose plaintext side is the
+    /// stdin and stdout of `openssl s_server`. `None` when openssl is
+    /// missing.
+    struct TlsServer {
+        child: Child,
+        port: u16,
+        ca_file: PathBuf,
+        _dir: TempDir,
+    }
+
+    impl TlsServer {
+        fn start(name: &str) -> Option<TlsServer> {
+            let dir = TempDir::new(name);
+            let (cert, key) = (dir.path().join("cert.pem"), dir.path().join("key.pem"));
+            let status = Command::new("openssl")
+                .args([
+                    "req", "-x509", "-newkey", "rsa:2048", "-nodes", "-days", "1",
+                ])
+                .args([
+                    "-subj",
+                    "/CN=localhost",
+                    "-addext",
+                    "subjectAltName=DNS:localhost",
+                ])
+                .arg("-keyout")
+                .arg(&key)
+                .arg("-out")
+                .arg(&cert)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
+            if !status.is_ok_and(|status| status.success()) {
+                eprintln!("openssl is not available, skipping");
+                return None;
+            }
+
+            let port = TcpListener::bind("127.0.0.1:0")
+                .and_then(|listener| listener.local_addr())
+                .unwrap()
+                .port();
+            let child = Command::new("openssl")
+                .args([
+                    "s_server",
+                    "-quiet",
+                    "-accept",
+                    &format!("127.0.0.1:{}", port),
+                ])
+                .arg("-cert")
+                .arg(&cert)
+                .arg("-key")
+                .arg(&key)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap();
+
+            // s_server carries on after the handshake of a probe fails.
+            for _ in 0..100 {
+                if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+
+            Some(TlsServer {
+                child,
+                port,
+                ca_file: cert,
+                _dir: dir,
+            })
+        }
+
+        fn stdin(&mut self) -> &mut ChildStdin {
+            self.child.stdin.as_mut().unwrap()
+        }
+
+        fn stdout(&mut self) -> &mut ChildStdout {
+            self.child.stdout.as_mut().unwrap()
+        }
+    }
+
+    impl Drop for TlsServer {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    fn proxy(upstream: Upstream, ca_file: &Path) -> Proxy {
+        Proxy {
+            ca_file: Some(ca_file.to_path_buf()),
+            ..Proxy::new(upstream)
+        }
+    }
+
+    #[test]
+    fn queries_are_forwarded_over_tls() {
+        let Some(mut server) = TlsServer::start("proxy-dot") else {
+            return;
+        };
+        let proxy = Arc::new(proxy(
+            Upstream::Tls {
+                addrs: vec![SocketAddr::new(
+                    IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    server.port,
+                )],
+                host: String::from("localhost"),
+            },
+            &server.ca_file,
+        ));
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let udp_proxy = Arc::clone(&proxy);
+        thread::spawn(move || serve_udp(&udp_proxy, socket, |_| {}));
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        client.send_to(&query(None), addr).unwrap();
+
+        let received = read_message(server.stdout()).unwrap();
+        assert_eq!(received, query(None));
+        write_message(server.stdin(), &response(&received, 16)).unwrap();
+
+        let mut buf = [0; 512];
+        let len = client.recv(&mut buf).unwrap();
+        assert_eq!(buf[..len], response(&query(None), 16));
+    }
+
+    #[test]
+    fn silent_upstream_addresses_are_skipped() {
+        let Some(mut server) = TlsServer::start("proxy-failover") else {
+            return;
+        };
+        // Connections to it are queued but never answered.
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = Arc::new(Proxy {
+            timeout: Duration::from_secs(1),
+            ..proxy(
+                Upstream::Tls {
+                    addrs: vec![
+                        silent.local_addr().unwrap(),
+                        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), server.port),
+                    ],
+                    host: String::from("localhost"),
+                },
+                &server.ca_file,
+            )
+        });
+
+        let resolving = Arc::clone(&proxy);
+        let client = thread::spawn(move || resolving.resolve(&query(None)));
+        let received = read_message(server.stdout()).unwrap();
+        write_message(server.stdin(), &response(&received, 0)).unwrap();
+
+        assert_eq!(client.join().unwrap().unwrap(), response(&query(None), 0));
+        assert_eq!(proxy.preferred.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn queries_are_forwarded_over_https() {
+        let Some(mut server) = TlsServer::start("proxy-doh") else {
+            return;
+        };
+        let proxy = proxy(
+            Upstream::Https {
+                url: format!("https://localhost:{}/dns-query", server.port),
+                addrs: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            },
+            &server.ca_file,
+        );
+
+        let client = thread::spawn(move || proxy.resolve(&query(None)));
+
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        let body = loop {
+            let len = server.stdout().read(&mut buf).unwrap();
+            assert!(len > 0, "connection closed");
+            request.extend_from_slice(&buf[..len]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some(end) = text.find("\r\n\r\n") {
+                if request.len() >= end + 4 + query(None).len() {
+                    break request[end + 4..].to_vec();
+                }
+            }
+        };
+        let head = String::from_utf8_lossy(&request).to_lowercase();
+        assert!(head.starts_with("post /dns-query http/1.1\r\n"));
+        assert!(head.contains("content-type: application/dns-message\r\n"));
+        assert_eq!(body[..2], [0, 0], "RFC 8484 asks for a zero ID");
+
+        let answer = response(&body, 0);
+        let mut reply = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/dns-message\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+            answer.len()
+        )
+        .into_bytes();
+        reply.extend_from_slice(&answer);
+        server.stdin().write_all(&reply).unwrap();
+        server.stdin().flush().unwrap();
+
+        let response = client.join().unwrap().unwrap();
+        assert_eq!(
+            response[..2],
+            [0x12, 0x34],
+            "the ID of the query is restored"
+        );
+        assert_eq!(response[2], 0x81);
+    }
+}
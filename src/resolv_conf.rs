@@ -0,0 +1,568 @@
+//! Typed model of resolv.conf(5) as read by the glibc resolver.
+//!
+//! Parsing never fails: lines the resolver would ignore are kept as
+//! [`Entry::Unknown`] and reported by [`ResolvConf::warnings`]. Serializing a
+//! parsed file gives back the exact original text, only entries which were
+//! added or changed are rendered anew.
+
+use std::fmt;
+use std::net::IpAddr;
+
+/// Where the resolver reads its configuration from.
+pub const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+
+/// The resolver only uses the first three nameservers (`MAXNS`).
+pub const MAX_NAMESERVERS: usize = 3;
+
+/// The resolver only uses the first ten sortlist pairs (`MAXRESOLVSORT`).
+pub const MAX_SORTLIST: usize = 10;
+
+/// Largest values the resolver accepts for numeric options, bigger ones are capped.
+const MAX_NDOTS: u32 = 15;
+const MAX_TIMEOUT: u32 = 30;
+const MAX_ATTEMPTS: u32 = 5;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Entry {
+    Nameserver {
+        addr: IpAddr,
+        scope: Option<String>,
+    },
+    Search(Vec<String>),
+    Domain(String),
+    Sortlist(Vec<String>),
+    Options(Vec<ResolverOption>),
+    /// A line starting with `#` or `;`, marker included.
+    Comment(String),
+    Blank,
+    /// A line the resolver ignores, e.g. an unknown keyword or an invalid address.
+    Unknown(String),
+}
+
+/// The `options` understood by glibc.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ResolverOption {
+    Debug,
+    Ndots(u32),
+    Timeout(u32),
+    Attempts(u32),
+    Rotate,
+    NoCheckNames,
+    Inet6,
+    Ip6Bytestring,
+    Ip6Dotint,
+    NoIp6Dotint,
+    Edns0,
+    SingleRequest,
+    SingleRequestReopen,
+    NoTldQuery,
+    UseVc,
+    NoReload,
+    TrustAd,
+    NoAaaa,
+    Unknown(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Line {
+    /// The original text of parsed lines, `None` for lines added afterwards.
+    raw: Option<String>,
+    entry: Entry,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResolvConf {
+    lines: Vec<Line>,
+    missing_final_newline: bool,
+}
+
+/// Something in the file the resolver will not do as written. Lines are
+/// numbered from 1.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Warning {
+    TooManyNameservers {
+        count: usize,
+    },
+    TooManySortlistPairs {
+        line: usize,
+        count: usize,
+    },
+    IgnoredLine {
+        line: usize,
+        text: String,
+    },
+    UnknownOption {
+        line: usize,
+        option: String,
+    },
+    DeprecatedOption {
+        line: usize,
+        option: String,
+    },
+    OptionCapped {
+        line: usize,
+        option: String,
+        max: u32,
+    },
+    /// `domain` and `search` override each other, the last one wins.
+    DomainAndSearch,
+}
+
+impl ResolvConf {
+    pub fn parse(content: &str) -> ResolvConf {
+        ResolvConf {
+            lines: content
+                .lines()
+                .map(|raw| Line {
+                    raw: Some(raw.to_string()),
+                    entry: Entry::parse(raw),
+                })
+                .collect(),
+            missing_final_newline: !content.is_empty() && !content.ends_with('\n'),
+        }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.lines.iter().map(|line| &line.entry)
+    }
+
+    pub fn nameservers(&self) -> impl Iterator<Item = &IpAddr> {
+        self.entries().filter_map(|entry| match entry {
+            Entry::Nameserver { addr, .. } => Some(addr),
+            _ => None,
+        })
+    }
+
+    /// Keeps the lines whose entry satisfies `f`.
+    pub fn retain(&mut self, mut f: impl FnMut(&Entry) -> bool) {
+        self.lines.retain(|line| f(&line.entry));
+    }
+
+    pub fn warnings(&self) -> Vec<Warning> {
+        let mut warnings = Vec::new();
+
+        for (i, line) in self.lines.iter().enumerate() {
+            let line_number = i + 1;
+            match &line.entry {
+                Entry::Unknown(text) => warnings.push(Warning::IgnoredLine {
+                    line: line_number,
+                    text: text.clone(),
+                }),
+                Entry::Sortlist(pairs) if pairs.len() > MAX_SORTLIST => {
+                    warnings.push(Warning::TooManySortlistPairs {
+                        line: line_number,
+                        count: pairs.len(),
+                    })
+                }
+                Entry::Options(options) => {
+                    for option in options {
+                        if let Some(warning) = option.warning(line_number) {
+                            warnings.push(warning);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let count = self.nameservers().count();
+        if count > MAX_NAMESERVERS {
+            warnings.push(Warning::TooManyNameservers { count });
+        }
+
+        let has = |f: fn(&Entry) -> bool| self.entries().any(f);
+        if has(|entry| matches!(entry, Entry::Domain(_)))
+            && has(|entry| matches!(entry, Entry::Search(_)))
+        {
+            warnings.push(Warning::DomainAndSearch);
+        }
+
+        warnings
+    }
+}
+
+impl fmt::Display for ResolvConf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, line) in self.lines.iter().enumerate() {
+            match &line.raw {
+                Some(raw) if Entry::parse(raw) == line.entry => write!(f, "{}", raw)?,
+                _ => write!(f, "{}", line.entry)?,
+            }
+            if i + 1 < self.lines.len() || !self.missing_final_newline {
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Entry {
+    /// Parses one line the way glibc does: keywords and comment markers must
+    /// start in the first column, followed by a space or a tab.
+    pub fn parse(line: &str) -> Entry {
+        if line.trim().is_empty() {
+            return Entry::Blank;
+        }
+        if line.starts_with('#') || line.starts_with(';') {
+            return Entry::Comment(line.to_string());
+        }
+
+        let (keyword, rest) = match line.find([' ', '\t']) {
+            Some(i) => (&line[..i], &line[i..]),
+            None => (line, ""),
+        };
+        let mut values = rest.split_whitespace();
+
+        match keyword {
+            "nameserver" => values
+                .next()
+                .and_then(parse_nameserver)
+                .unwrap_or_else(|| Entry::Unknown(line.to_string())),
+            "domain" => match values.next() {
+                Some(domain) => Entry::Domain(domain.to_string()),
+                None => Entry::Unknown(line.to_string()),
+            },
+            "search" => Entry::Search(values.map(String::from).collect()),
+            "sortlist" => Entry::Sortlist(values.map(String::from).collect()),
+            "options" => Entry::Options(values.map(ResolverOption::parse).collect()),
+            _ => Entry::Unknown(line.to_string()),
+        }
+    }
+}
+
+fn parse_nameserver(value: &str) -> Option<Entry> {
+    let (addr, scope) = match value.split_once('%') {
+        Some((addr, scope)) => (addr, Some(scope.to_string())),
+        None => (value, None),
+    };
+
+    let addr: IpAddr = addr.parse().ok()?;
+    if scope.is_some() && addr.is_ipv4() {
+        return None;
+    }
+    Some(Entry::Nameserver { addr, scope })
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Entry::Nameserver { addr, scope } => match scope {
+                Some(scope) => write!(f, "nameserver {}%{}", addr, scope),
+                None => write!(f, "nameserver {}", addr),
+            },
+            Entry::Search(domains) => write!(f, "search {}", domains.join(" ")),
+            Entry::Domain(domain) => write!(f, "domain {}", domain),
+            Entry::Sortlist(pairs) => write!(f, "sortlist {}", pairs.join(" ")),
+            Entry::Options(options) => {
+                write!(f, "options")?;
+                for option in options {
+                    write!(f, " {}", option)?;
+                }
+                Ok(())
+            }
+            Entry::Comment(text) | Entry::Unknown(text) => write!(f, "{}", text),
+            Entry::Blank => Ok(()),
+        }
+    }
+}
+
+impl ResolverOption {
+    pub fn parse(option: &str) -> ResolverOption {
+        let numeric = |value: &str, variant: fn(u32) -> ResolverOption| match value.parse() {
+            Ok(value) => variant(value),
+            Err(_) => ResolverOption::Unknown(option.to_string()),
+        };
+
+        match option.split_once(':') {
+            Some(("ndots", value)) => numeric(value, ResolverOption::Ndots),
+            Some(("timeout", value)) => numeric(value, ResolverOption::Timeout),
+            Some(("attempts", value)) => numeric(value, ResolverOption::Attempts),
+            _ => match option {
+                "debug" => ResolverOption::Debug,
+                "rotate" => ResolverOption::Rotate,
+                "no-check-names" => ResolverOption::NoCheckNames,
+                "inet6" => ResolverOption::Inet6,
+                "ip6-bytestring" => ResolverOption::Ip6Bytestring,
+                "ip6-dotint" => ResolverOption::Ip6Dotint,
+                "no-ip6-dotint" => ResolverOption::NoIp6Dotint,
+                "edns0" => ResolverOption::Edns0,
+                "single-request" => ResolverOption::SingleRequest,
+                "single-request-reopen" => ResolverOption::SingleRequestReopen,
+                "no-tld-query" | "no_tld_query" => ResolverOption::NoTldQuery,
+                "use-vc" => ResolverOption::UseVc,
+                "no-reload" => ResolverOption::NoReload,
+                "trust-ad" => ResolverOption::TrustAd,
+                "no-aaaa" => ResolverOption::NoAaaa,
+                _ => ResolverOption::Unknown(option.to_string()),
+            },
+        }
+    }
+
+    fn warning(&self, line: usize) -> Option<Warning> {
+        let capped = |value: u32, max: u32| {
+            (value > max).then(|| Warning::OptionCapped {
+                line,
+                option: self.to_string(),
+                max,
+            })
+        };
+
+        match self {
+            ResolverOption::Ndots(value) => capped(*value, MAX_NDOTS),
+            ResolverOption::Timeout(value) => capped(*value, MAX_TIMEOUT),
+            ResolverOption::Attempts(value) => capped(*value, MAX_ATTEMPTS),
+            ResolverOption::Inet6
+            | ResolverOption::Ip6Bytestring
+            | ResolverOption::Ip6Dotint
+            | ResolverOption::NoIp6Dotint => Some(Warning::DeprecatedOption {
+                line,
+                option: self.to_string(),
+            }),
+            ResolverOption::Unknown(option) => Some(Warning::UnknownOption {
+                line,
+                option: option.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ResolverOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResolverOption::Debug => write!(f, "debug"),
+            ResolverOption::Ndots(value) => write!(f, "ndots:{}", value),
+            ResolverOption::Timeout(value) => write!(f, "timeout:{}", value),
+            ResolverOption::Attempts(value) => write!(f, "attempts:{}", value),
+            ResolverOption::Rotate => write!(f, "rotate"),
+            ResolverOption::NoCheckNames => write!(f, "no-check-names"),
+            ResolverOption::Inet6 => write!(f, "inet6"),
+            ResolverOption::Ip6Bytestring => write!(f, "ip6-bytestring"),
+            ResolverOption::Ip6Dotint => write!(f, "ip6-dotint"),
+            ResolverOption::NoIp6Dotint => write!(f, "no-ip6-dotint"),
+            ResolverOption::Edns0 => write!(f, "edns0"),
+            ResolverOption::SingleRequest => write!(f, "single-request"),
+            ResolverOption::SingleRequestReopen => write!(f, "single-request-reopen"),
+            ResolverOption::NoTldQuery => write!(f, "no-tld-query"),
+            ResolverOption::UseVc => write!(f, "use-vc"),
+            ResolverOption::NoReload => write!(f, "no-reload"),
+            ResolverOption::TrustAd => write!(f, "trust-ad"),
+            ResolverOption::NoAaaa => write!(f, "no-aaaa"),
+            ResolverOption::Unknown(option) => write!(f, "{}", option),
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::TooManyNameservers { count } => write!(
+                f,
+                "{} nameservers listed, the resolver only uses the first {}",
+                count, MAX_NAMESERVERS
+            ),
+            Warning::TooManySortlistPairs { line, count } => write!(
+                f,
+                "line {}: {} sortlist pairs listed, the resolver only uses the first {}",
+                line, count, MAX_SORTLIST
+            ),
+            Warning::IgnoredLine { line, text } => {
+                write!(f, "line {}: ignored by the resolver: {}", line, text)
+            }
+            Warning::UnknownOption { line, option } => {
+                write!(f, "line {}: unknown option `{}`", line, option)
+            }
+            Warning::DeprecatedOption { line, option } => write!(
+                f,
+                "line {}: option `{}` is no longer supported by glibc",
+                line, option
+            ),
+            Warning::OptionCapped { line, option, max } => write!(
+                f,
+                "line {}: option `{}` is capped to {} by the resolver",
+                line, option, max
+            ),
+            Warning::DomainAndSearch => write!(
+                f,
+                "both `domain` and `search` are set, only the last one is used"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const SAMPLE: &str = "\
+# Generated by resolvconf
+; old style comment
+nameserver 94.140.14.14
+nameserver\t2a10:50c0::ad1:ff
+nameserver fe80::1%eth0
+
+search example.org  corp.example.org
+options ndots:2 timeout:3 attempts:2 rotate edns0 no_tld_query trust-ad
+sortlist 130.155.160.0/255.255.240.0 130.155.0.0
+";
+
+    #[test]
+    fn parse_and_serialize_round_trip() {
+        let inputs = [
+            SAMPLE,
+            "",
+            "\n\n",
+            "nameserver 1.1.1.1",
+            "  nameserver 1.1.1.1\nbogus line\noptions   edns0   \n",
+            "options ndots:x unknown-opt inet6\n",
+        ];
+
+        for input in inputs {
+            assert_eq!(ResolvConf::parse(input).to_string(), input);
+        }
+    }
+
+    #[test]
+    fn parse_every_keyword() {
+        let conf = ResolvConf::parse(SAMPLE);
+        let entries: Vec<_> = conf.entries().cloned().collect();
+
+        assert_eq!(
+            entries,
+            vec![
+                Entry::Comment(String::from("# Generated by resolvconf")),
+                Entry::Comment(String::from("; old style comment")),
+                Entry::Nameserver {
+                    addr: IpAddr::V4(Ipv4Addr::new(94, 140, 14, 14)),
+                    scope: None
+                },
+                Entry::Nameserver {
+                    addr: IpAddr::V6(Ipv6Addr::new(0x2a10, 0x50c0, 0, 0, 0, 0, 0xad1, 0xff)),
+                    scope: None
+                },
+                Entry::Nameserver {
+                    addr: IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1)),
+                    scope: Some(String::from("eth0"))
+                },
+                Entry::Blank,
+                Entry::Search(vec![
+                    String::from("example.org"),
+                    String::from("corp.example.org")
+                ]),
+                Entry::Options(vec![
+                    ResolverOption::Ndots(2),
+                    ResolverOption::Timeout(3),
+                    ResolverOption::Attempts(2),
+                    ResolverOption::Rotate,
+                    ResolverOption::Edns0,
+                    ResolverOption::NoTldQuery,
+                    ResolverOption::TrustAd,
+                ]),
+                Entry::Sortlist(vec![
+                    String::from("130.155.160.0/255.255.240.0"),
+                    String::from("130.155.0.0")
+                ]),
+            ]
+        );
+        assert!(conf.warnings().is_empty());
+    }
+
+    #[test]
+    fn every_option_round_trips_through_display() {
+        let options = "debug ndots:1 timeout:5 attempts:3 rotate no-check-names inet6 \
+            ip6-bytestring ip6-dotint no-ip6-dotint edns0 single-request \
+            single-request-reopen no-tld-query use-vc no-reload trust-ad no-aaaa";
+
+        for option in options.split_whitespace() {
+            let parsed = ResolverOption::parse(option);
+            assert!(!matches!(parsed, ResolverOption::Unknown(_)), "{}", option);
+            assert_eq!(parsed.to_string(), option);
+        }
+    }
+
+    #[test]
+    fn retain_keeps_the_raw_text_of_kept_lines() {
+        let mut conf = ResolvConf::parse("nameserver 1.1.1.1\nsearch  example.org\noptions edns0");
+
+        conf.retain(|entry| !matches!(entry, Entry::Nameserver { .. }));
+
+        assert_eq!(conf.to_string(), "search  example.org\noptions edns0");
+    }
+
+    #[test]
+    fn domain_and_search_are_reported() {
+        let conf = ResolvConf::parse("search a.example b.example\ndomain c.example\n");
+
+        assert_eq!(conf.warnings(), vec![Warning::DomainAndSearch]);
+    }
+
+    #[test]
+    fn lines_ignored_by_the_resolver_are_reported() {
+        let conf = ResolvConf::parse(
+            "nameserver not-an-ip\n nameserver 1.1.1.1\nnameserver 1.1.1.1%eth0\nfoo bar\n",
+        );
+
+        assert_eq!(conf.nameservers().count(), 0);
+        let warnings = conf.warnings();
+        assert_eq!(warnings.len(), 4);
+        assert!(warnings
+            .iter()
+            .all(|warning| matches!(warning, Warning::IgnoredLine { .. })));
+        assert_eq!(
+            warnings[0].to_string(),
+            "line 1: ignored by the resolver: nameserver not-an-ip"
+        );
+    }
+
+    #[test]
+    fn more_than_three_nameservers_is_reported() {
+        let conf = ResolvConf::parse(
+            "nameserver 1.1.1.1\nnameserver 1.0.0.1\nnameserver 9.9.9.9\nnameserver 8.8.8.8\n",
+        );
+
+        assert_eq!(
+            conf.warnings(),
+            vec![Warning::TooManyNameservers { count: 4 }]
+        );
+    }
+
+    #[test]
+    fn option_warnings() {
+        let conf = ResolvConf::parse("options ndots:20 timeout:3 ip6-dotint frobnicate ndots:x\n");
+
+        assert_eq!(
+            conf.warnings(),
+            vec![
+                Warning::OptionCapped {
+                    line: 1,
+                    option: String::from("ndots:20"),
+                    max: MAX_NDOTS
+                },
+                Warning::DeprecatedOption {
+                    line: 1,
+                    option: String::from("ip6-dotint")
+                },
+                Warning::UnknownOption {
+                    line: 1,
+                    option: String::from("frobnicate")
+                },
+                Warning::UnknownOption {
+                    line: 1,
+                    option: String::from("ndots:x")
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn too_many_sortlist_pairs_is_reported() {
+        let pairs: Vec<_> = (0..11).map(|i| format!("10.{}.0.0", i)).collect();
+        let conf = ResolvConf::parse(&format!("sortlist {}\n", pairs.join(" ")));
+
+        assert_eq!(
+            conf.warnings(),
+            vec![Warning::TooManySortlistPairs { line: 1, count: 11 }]
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+
+use crate::backend::{self, Configured};
+use crate::config;
+use crate::error::Error;
+use crate::provider::{self, Provider};
+use crate::proxy;
+use crate::resolv_conf::{ResolvConf, Warning, RESOLV_CONF_PATH};
+use crate::system::System;
+
+const RESOLVCONF_INTERFACE_DIR: &str = "/run/resolvconf/interface";
+
+/// The upstream servers systemd-resolved forwards the queries of its stub to.
+const RESOLVED_UPSTREAM_PATH: &str = "/run/systemd/resolve/resolv.conf";
+const RESOLVED_STUB_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 53));
+
+/// A nameserver read from one of the resolver files.
+#[derive(Debug, PartialEq)]
+pub struct Nameserver {
+    pub addr: IpAddr,
+    pub provider: Option<&'static Provider>,
+}
+
+impl Nameserver {
+    fn new(addr: IpAddr) -> Nameserver {
+        Nameserver {
+            addr,
+            provider: provider::find_by_nameserver(&addr),
+        }
+    }
+}
+
+/// A file read by the status check with the nameservers it lists.
+#[derive(Debug, PartialEq)]
+pub struct Source {
+    pub path: PathBuf,
+    pub nameservers: Vec<Nameserver>,
+    pub warnings: Vec<Warning>,
+}
+
+impl Source {
+    fn parse(path: &Path, content: &str) -> Source {
+        let conf = ResolvConf::parse(content);
+        Source {
+            path: path.to_path_buf(),
+            nameservers: conf.nameservers().copied().map(Nameserver::new).collect(),
+            warnings: conf.warnings(),
+        }
+    }
+}
+
+impl From<Configured> for Source {
+    fn from(configured: Configured) -> Source {
+        Source {
+            path: configured.path,
+            nameservers: configured
+                .nameservers
+                .into_iter()
+                .map(Nameserver::new)
+                .collect(),
+            warnings: Vec::new(),
+        }
+    }
+}
+
+/// What the resolver files say about the configured DNS provider.
+#[derive(Debug, PartialEq)]
+pub struct Status {
+    /// The files in which a backend configured nameservers.
+    pub configured: Vec<Source>,
+    /// The resolv.conf read by the resolver, `None` when it does not exist.
+    pub resolv_conf: Option<Source>,
+    /// The servers behind the systemd-resolved stub, when resolv.conf points at it.
+    pub resolved_upstream: Option<Source>,
+    /// The nameservers resolvconf received from each interface, e.g. through DHCP.
+    pub interfaces: Vec<Source>,
+    /// The provider `cfg-adguard-dns proxy` forwards to according to the
+    /// configuration file, when a source lists the proxy.
+    pub proxy_upstream: Option<&'static Provider>,
+}
+
+/// Computes the status from the files only, without any network access.
+pub fn check(system: &System) -> Result<Status, Error> {
+    let mut configured = Vec::new();
+    for backend in backend::BACKENDS {
+        configured.extend(backend.configured(system)?.into_iter().map(Source::from));
+    }
+
+    let resolv_conf = read(system, RESOLV_CONF_PATH)?;
+    let uses_stub = resolv_conf.as_ref().is_some_and(|source| {
+        source
+            .nameservers
+            .first()
+            .is_some_and(|nameserver| nameserver.addr == RESOLVED_STUB_ADDR)
+    });
+    let resolved_upstream = match uses_stub {
+        true => read(system, RESOLVED_UPSTREAM_PATH)?,
+        false => None,
+    };
+
+    let mut interfaces = Vec::new();
+    match fs::read_dir(system.path(RESOLVCONF_INTERFACE_DIR)?) {
+        Ok(entries) => {
+            for entry in entries {
+                let path = entry?.path();
+                let content = fs::read_to_string(&path)?;
+                interfaces.push(Source::parse(&path, &content));
+            }
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    interfaces.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let lists_proxy = configured
+        .iter()
+        .chain(resolv_conf.iter())
+        .chain(resolved_upstream.iter())
+        .flat_map(|source| &source.nameservers)
+        .any(is_proxy);
+    let proxy_upstream = match lists_proxy {
+        true => Some(config::read(system)?.proxy_provider()),
+        false => None,
+    };
+
+    Ok(Status {
+        configured,
+        resolv_conf,
+        resolved_upstream,
+        interfaces,
+        proxy_upstream,
+    })
+}
+
+fn is_proxy(nameserver: &Nameserver) -> bool {
+    nameserver.addr == proxy::DEFAULT_LISTEN_ADDR.ip()
+}
+
+impl Status {
+    /// The provider the resolver actually queries first, if it is a known one.
+    pub fn active_provider(&self) -> Option<&'static Provider> {
+        self.active_nameserver()
+            .and_then(|nameserver| self.provider(nameserver))
+    }
+
+    /// Whether the resolver queries the provider through `cfg-adguard-dns proxy`.
+    pub fn is_proxied(&self) -> bool {
+        self.active_nameserver().is_some_and(is_proxy)
+    }
+
+    /// The provider configured by one of the backends, if any.
+    pub fn configured_provider(&self) -> Option<&'static Provider> {
+        self.configured
+            .iter()
+            .flat_map(|source| &source.nameservers)
+            .find_map(|nameserver| self.provider(nameserver))
+    }
+
+    fn active_nameserver(&self) -> Option<&Nameserver> {
+        self.resolved_upstream
+            .as_ref()
+            .or(self.resolv_conf.as_ref())
+            .and_then(|source| source.nameservers.first())
+    }
+
+    /// The provider answering `nameserver`'s queries, the proxy's upstream for the proxy.
+    fn provider(&self, nameserver: &Nameserver) -> Option<&'static Provider> {
+        match is_proxy(nameserver) {
+            true => self.proxy_upstream,
+            false => nameserver.provider,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // With the resolv-conf backend, resolv.conf is already a configured source.
+        let resolv_conf = self.resolv_conf.iter().filter(|source| {
+            !self
+                .configured
+                .iter()
+                .any(|configured| configured.path == source.path)
+        });
+        let sources = self
+            .configured
+            .iter()
+            .chain(resolv_conf)
+            .chain(self.resolved_upstream.iter())
+            .chain(self.interfaces.iter());
+        for source in sources {
+            writeln!(f, "{}:", source.path.display())?;
+            if source.nameservers.is_empty() {
+                writeln!(f, "        no nameserver")?;
+            }
+            for nameserver in &source.nameservers {
+                match nameserver.provider {
+                    Some(provider) => writeln!(
+                        f,
+                        "        {:<24}{}",
+                        nameserver.addr.to_string(),
+                        provider.label
+                    )?,
+                    None if is_proxy(nameserver) => writeln!(
+                        f,
+                        "        {:<24}cfg-adguard-dns proxy",
+                        nameserver.addr.to_string()
+                    )?,
+                    None => writeln!(f, "        {}", nameserver.addr)?,
+                }
+            }
+            for warning in &source.warnings {
+                writeln!(f, "        warning: {}", warning)?;
+            }
+        }
+
+        match (self.active_provider(), self.configured_provider()) {
+            (Some(active), _) if self.is_proxied() => write!(
+                f,
+                "{} is activated through the cfg-adguard-dns proxy",
+                active.label
+            ),
+            (Some(active), _) => write!(f, "{} is activated", active.label),
+            (None, Some(configured)) => {
+                write!(f, "{} is configured but not in use yet", configured.label)
+            }
+            (None, None) => write!(f, "No known DNS provider is activated"),
+        }
+    }
+}
+
+fn read(system: &System, path: &str) -> Result<Option<Source>, Error> {
+    match system.read(path)? {
+        Some(content) => Ok(Some(Source::parse(&system.path(path)?, &content))),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::direct::Direct;
+    use crate::backend::Backend;
+    use crate::testutil::{self, TempDir};
+
+    fn check_fixture(name: &str) -> Status {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/status")
+            .join(name);
+        check(&System::with_root(&root, None)).unwrap()
+    }
+
+    #[test]
+    fn activated_provider_is_the_first_nameserver_in_use() {
+        let status = check_fixture("activated");
+
+        assert_eq!(status.active_provider().unwrap().name, "quad9");
+        assert_eq!(status.configured_provider().unwrap().name, "quad9");
+        assert_eq!(status.interfaces.len(), 1);
+        assert_eq!(
+            status.interfaces[0].nameservers[0].addr.to_string(),
+            "192.168.1.1"
+        );
+        assert!(status.to_string().ends_with("Quad9 is activated"));
+    }
+
+    #[test]
+    fn configured_provider_not_yet_in_use() {
+        let status = check_fixture("pending");
+
+        assert!(status.active_provider().is_none());
+        assert_eq!(status.configured_provider().unwrap().name, "adguard");
+        assert!(status.to_string().contains("not in use"));
+    }
+
+    #[test]
+    fn deactivated_without_block_or_known_nameserver() {
+        let status = check_fixture("deactivated");
+
+        assert!(status.configured.is_empty());
+        assert!(status.resolved_upstream.is_none());
+        assert!(status.interfaces.is_empty());
+        assert_eq!(status.resolv_conf.as_ref().unwrap().warnings.len(), 1);
+        assert!(status
+            .to_string()
+            .ends_with("No known DNS provider is activated"));
+    }
+
+    #[test]
+    fn proxy_is_activated_with_the_configured_upstream() {
+        let status = check_fixture("proxied");
+
+        assert!(status.is_proxied());
+        assert_eq!(status.active_provider().unwrap().name, "quad9");
+        assert_eq!(status.configured_provider().unwrap().name, "quad9");
+        assert!(status
+            .to_string()
+            .ends_with("Quad9 is activated through the cfg-adguard-dns proxy"));
+    }
+
+    #[test]
+    fn resolv_conf_of_the_resolv_conf_backend_is_listed_once() -> Result<(), Error> {
+        let root = TempDir::new("status-direct");
+        let system = System::with_root(root.path(), None);
+        system.write(RESOLV_CONF_PATH, "nameserver 192.168.1.1\n")?;
+        Direct.activate(&system, &testutil::profile("quad9"))?;
+
+        let status = check(&system)?;
+
+        assert_eq!(status.configured[0].path, system.path(RESOLV_CONF_PATH)?);
+        assert_eq!(status.active_provider().unwrap().name, "quad9");
+        let listing = status.to_string();
+        assert_eq!(listing.matches("resolv.conf:").count(), 1, "{}", listing);
+        Ok(())
+    }
+
+    #[test]
+    fn resolved_stub_is_looked_through() {
+        let status = check_fixture("resolved");
+
+        assert_eq!(status.active_provider().unwrap().name, "adguard-unfiltered");
+        assert_eq!(
+            status.configured_provider().unwrap().name,
+            "adguard-unfiltered"
+        );
+        assert_eq!(status.configured[0].nameservers.len(), 2);
+    }
+}
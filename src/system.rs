@@ -0,0 +1,775 @@
+use std::cell::RefCell;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, ExitStatus, Output};
+
+use crate::error::Error;
+use crate::fsutil;
+use crate::journal::{self, Journal};
+
+const STATE_DIR_ENV_VAR: &str = "CFG_ADGUARD_DNS_STATE_DIR";
+const STATE_DIR_DEFAULT_PATH: &str = "/var/lib/cfg-adguard-dns";
+
+/// The machine being configured: every file the backends read or write and
+/// every command they run goes through it.
+///
+/// Paths are given as they are on the configured machine, e.g.
+/// `/etc/resolv.conf`, and are looked up below `root`, symlinks included.
+pub struct System {
+    root: PathBuf,
+    bin_dir: Option<PathBuf>,
+    /// Whether the machine below `root` is the one running, whose services
+    /// are reloaded and asked about their state.
+    running: bool,
+    /// The journal of the transaction in progress, if any.
+    journal: RefCell<Option<Journal>>,
+    /// What a dry run would have done so far, `None` outside of dry runs.
+    preview: Option<RefCell<Preview>>,
+    /// What the user should be told about the changes, not yet taken.
+    notes: RefCell<Vec<Note>>,
+}
+
+/// What a dry run would have changed, in order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Preview {
+    /// The files that would change, each listed once.
+    pub changes: Vec<Change>,
+    /// The command lines that would run, e.g. `resolvconf -u`.
+    pub commands: Vec<String>,
+}
+
+/// A file a dry run would have changed, with its content before the run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// The path on the configured machine.
+    pub path: PathBuf,
+    /// The content before the run, `None` if the file did not exist.
+    pub before: Option<String>,
+    pub after: Pending,
+}
+
+/// What a file would become.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pending {
+    File(String),
+    Symlink(PathBuf),
+    Absent,
+}
+
+/// Something the user should be told about a change besides its outcome,
+/// e.g. that a backup was saved.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Note {
+    Info(String),
+    /// Something which may need attention, e.g. what a command which
+    /// succeeded reported on stderr.
+    Warning(String),
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Note::Info(message) => write!(f, "{}", message),
+            Note::Warning(message) => write!(f, "warning: {}", message),
+        }
+    }
+}
+
+impl Default for System {
+    fn default() -> System {
+        System::new()
+    }
+}
+
+impl System {
+    /// The machine the tool runs on.
+    pub fn new() -> System {
+        System {
+            root: PathBuf::from("/"),
+            bin_dir: None,
+            running: true,
+            journal: RefCell::new(None),
+            preview: None,
+            notes: RefCell::default(),
+        }
+    }
+
+    /// A machine whose root file system is mounted at `root`, e.g. a chroot
+    /// or a disk image being built. Its services are not running, so they
+    /// are not reloaded: they read the files when the machine boots.
+    pub fn at_root(root: &Path) -> System {
+        System {
+            root: root.to_path_buf(),
+            running: false,
+            ..System::new()
+        }
+    }
+
+    /// Turns this into a dry run: files are read from the machine but
+    /// changes to them are only kept in memory, and commands are only
+    /// recorded, see [`System::preview`].
+    pub fn dry_run(mut self) -> System {
+        self.preview = Some(RefCell::default());
+        self
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.preview.is_some()
+    }
+
+    /// Whether the machine is the one running, whose services can be asked
+    /// about their state, see [`System::at_root`].
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Whether changes take effect right away, i.e. services are reloaded
+    /// and not in a dry run.
+    pub fn is_live(&self) -> bool {
+        self.running && self.preview.is_none()
+    }
+
+    /// What the dry run would have done so far, `None` outside of dry runs.
+    pub fn preview(&self) -> Option<Preview> {
+        self.preview
+            .as_ref()
+            .map(|preview| preview.borrow().clone())
+    }
+
+    /// Tells the user about a change, see [`System::take_notes`].
+    pub fn note(&self, note: Note) {
+        self.notes.borrow_mut().push(note);
+    }
+
+    /// What the user should be told about the changes so far, whether they
+    /// succeeded or not, in order. The library prints nothing itself.
+    pub fn take_notes(&self) -> Vec<Note> {
+        self.notes.take()
+    }
+
+    /// A machine whose files live below `root` and whose commands are looked
+    /// up in `bin_dir` first, e.g. to run against fixtures and fake commands.
+    #[cfg(test)]
+    pub fn with_root(root: &Path, bin_dir: Option<&Path>) -> System {
+        System {
+            bin_dir: bin_dir.map(Path::to_path_buf),
+            running: true,
+            ..System::at_root(root)
+        }
+    }
+
+    /// Where `path` is found from this process, its symlinks followed as
+    /// [`System::read`] does.
+    pub fn path(&self, path: impl AsRef<Path>) -> Result<PathBuf, Error> {
+        Ok(self.host(&self.resolve(path.as_ref(), true)?))
+    }
+
+    /// Where a path with no symlinks left to follow is found from this
+    /// process.
+    fn host(&self, path: &Path) -> PathBuf {
+        self.root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    /// Where the tool keeps its own files, such as backups, as a path on the
+    /// configured machine.
+    pub fn state_dir(&self) -> PathBuf {
+        match env::var(STATE_DIR_ENV_VAR) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => PathBuf::from(STATE_DIR_DEFAULT_PATH),
+        }
+    }
+
+    /// Reads a file, `None` meaning that it does not exist.
+    pub fn read(&self, path: impl AsRef<Path>) -> Result<Option<String>, Error> {
+        let path = self.resolve(path.as_ref(), true)?;
+        if let Some(pending) = self.pending(&path) {
+            return Ok(match pending {
+                Pending::File(content) => Some(content),
+                _ => None,
+            });
+        }
+
+        let path = self.host(&path);
+        match fs::read_to_string(&path) {
+            Ok(content) => Ok(Some(content)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) if err.kind() == ErrorKind::PermissionDenied => Err(Error::PermissionDenied {
+                what: format!("cannot read {}", path.display()),
+            }),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Atomically replaces a file, creating its parent directories if needed.
+    pub fn write(&self, path: impl AsRef<Path>, content: &str) -> Result<(), Error> {
+        self.write_file(path.as_ref(), content, None)
+    }
+
+    /// Like [`System::write`], the file getting `mode`, e.g. `0o600`, as
+    /// soon as it is created.
+    pub fn write_with_mode(
+        &self,
+        path: impl AsRef<Path>,
+        content: &str,
+        mode: u32,
+    ) -> Result<(), Error> {
+        self.write_file(path.as_ref(), content, Some(mode))
+    }
+
+    fn write_file(&self, path: &Path, content: &str, mode: Option<u32>) -> Result<(), Error> {
+        let path = self.resolve(path, true)?;
+        if self.preview.is_some() {
+            return self.change(&path, Pending::File(content.to_string()));
+        }
+        let path = self.host(&path);
+        self.record(&path)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| write_failed(parent, err))?;
+        }
+        fsutil::replace_atomic_with_mode(&path, content, mode)
+            .map_err(|err| write_failed(&path, err))
+    }
+
+    /// Atomically replaces a file, or the symlink at `path` rather than the
+    /// file it points to.
+    pub fn replace(&self, path: impl AsRef<Path>, content: &str) -> Result<(), Error> {
+        let path = self.resolve(path.as_ref(), false)?;
+        if self.preview.is_some() {
+            return self.change(&path, Pending::File(content.to_string()));
+        }
+        let path = self.host(&path);
+        self.record(&path)?;
+        fsutil::replace_atomic(&path, content).map_err(|err| write_failed(&path, err))
+    }
+
+    /// Reads where a symlink points, `None` meaning that `path` is not one.
+    pub fn read_link(&self, path: impl AsRef<Path>) -> Result<Option<PathBuf>, Error> {
+        self.link_at(&self.resolve(path.as_ref(), false)?)
+    }
+
+    /// [`System::read_link`] of a path whose parent is resolved.
+    fn link_at(&self, path: &Path) -> Result<Option<PathBuf>, Error> {
+        if let Some(pending) = self.pending(path) {
+            return Ok(match pending {
+                Pending::Symlink(target) => Some(target),
+                _ => None,
+            });
+        }
+        Ok(fsutil::read_link(&self.host(path))?)
+    }
+
+    /// Atomically makes `path` a symlink to `target`.
+    pub fn symlink(&self, target: impl AsRef<Path>, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = self.resolve(path.as_ref(), false)?;
+        if self.preview.is_some() {
+            let target = target.as_ref().to_path_buf();
+            return self.change(&path, Pending::Symlink(target));
+        }
+        let path = self.host(&path);
+        self.record(&path)?;
+        fsutil::symlink_atomic(target.as_ref(), &path).map_err(|err| write_failed(&path, err))
+    }
+
+    /// Lists the entries of a directory, sorted, as paths on the configured
+    /// machine. A missing directory has no entries.
+    pub fn read_dir(&self, path: impl AsRef<Path>) -> Result<Vec<PathBuf>, Error> {
+        let path = path.as_ref();
+        let entries = match fs::read_dir(self.path(path)?) {
+            Ok(entries) => entries.collect::<Result<Vec<_>, _>>()?,
+            Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut paths = Vec::new();
+        for entry in entries {
+            paths.push(path.join(entry.file_name()));
+        }
+        if let Some(preview) = &self.preview {
+            for change in &preview.borrow().changes {
+                if change.path.parent() == Some(path) && !paths.contains(&change.path) {
+                    paths.push(change.path.clone());
+                }
+                if change.after == Pending::Absent {
+                    paths.retain(|path| *path != change.path);
+                }
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Removes a file and tells whether it existed.
+    pub fn remove(&self, path: impl AsRef<Path>) -> Result<bool, Error> {
+        let path = self.resolve(path.as_ref(), false)?;
+        if self.preview.is_some() {
+            let existed = match self.pending(&path) {
+                Some(pending) => pending != Pending::Absent,
+                None => fs::symlink_metadata(self.host(&path)).is_ok(),
+            };
+            if existed {
+                self.change(&path, Pending::Absent)?;
+            }
+            return Ok(existed);
+        }
+        let path = self.host(&path);
+        self.record(&path)?;
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(write_failed(&path, err)),
+        }
+    }
+
+    /// Runs a command making a service read the files again. Within a
+    /// transaction, it is run again after a rollback. Skipped when the
+    /// machine is not the one running.
+    pub fn reload(&self, program: &str, args: &[&str]) -> Result<Output, Error> {
+        if !self.running {
+            return Ok(success());
+        }
+        if let Some(journal) = self.journal.borrow_mut().as_mut() {
+            journal.record_reload(program, args)?;
+        }
+        self.run(program, args)
+    }
+
+    /// Runs `f` as a transaction over the files it changes: if it fails,
+    /// they are put back as they were and the services reloaded. The prior
+    /// state is journaled on disk until `f` returns, so that [`System::recover`]
+    /// can also roll back after a crash. A rollback which fails too is
+    /// reported along with the error of `f`.
+    pub fn transaction<T>(&self, f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+        if self.journal.borrow().is_some() || self.preview.is_some() {
+            return f();
+        }
+        let dir = self.journal_dir()?;
+        let journal = match Journal::create(&dir) {
+            Ok(journal) => journal,
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                return Err(Error::Busy {
+                    pid: journal::owner(&dir)?,
+                })
+            }
+            Err(err) => return Err(write_failed(&dir, err)),
+        };
+        *self.journal.borrow_mut() = Some(journal);
+
+        let result = f();
+        let journal = self
+            .journal
+            .take()
+            .expect("the journal is kept until the end");
+        match result {
+            Ok(value) => {
+                journal.commit()?;
+                Ok(value)
+            }
+            Err(err) => {
+                drop(journal);
+                match self.roll_back() {
+                    Ok(_) => Err(err),
+                    Err(rollback) => Err(Error::RollbackFailed {
+                        cause: Box::new(err),
+                        rollback: Box::new(rollback),
+                    }),
+                }
+            }
+        }
+    }
+
+    /// Rolls back a transaction left over by a process that died, telling
+    /// whether there was one. A dry run leaves it for the next real run.
+    pub fn recover(&self) -> Result<bool, Error> {
+        if self.preview.is_some() {
+            return Ok(false);
+        }
+        let dir = self.journal_dir()?;
+        if let Some(pid) = journal::owner(&dir)? {
+            if pid != process::id() && Path::new("/proc").join(pid.to_string()).exists() {
+                return Err(Error::Busy { pid: Some(pid) });
+            }
+        }
+        self.roll_back()
+    }
+
+    fn roll_back(&self) -> Result<bool, Error> {
+        let rolled_back = journal::roll_back(&self.journal_dir()?, |program, args| {
+            self.run(program, args).map(|_| ())
+        })?;
+        Ok(rolled_back)
+    }
+
+    fn journal_dir(&self) -> Result<PathBuf, Error> {
+        self.path(self.state_dir().join("journal"))
+    }
+
+    /// Journals the state of `path`, a path below the root, if a
+    /// transaction is in progress.
+    fn record(&self, path: &Path) -> Result<(), Error> {
+        match self.journal.borrow_mut().as_mut() {
+            Some(journal) => Ok(journal.record(path)?),
+            None => Ok(()),
+        }
+    }
+
+    /// What a dry run would have made of `path` so far, if anything.
+    fn pending(&self, path: &Path) -> Option<Pending> {
+        let preview = self.preview.as_ref()?.borrow();
+        preview
+            .changes
+            .iter()
+            .find(|change| change.path == path)
+            .map(|change| change.after.clone())
+    }
+
+    /// Follows the symlinks in `path`, the last one only if `follow_last`,
+    /// as a chroot would: absolute targets and `..` stay below the root. In
+    /// a dry run, symlinks are taken as they would be after it so far.
+    fn resolve(&self, path: &Path, follow_last: bool) -> Result<PathBuf, Error> {
+        fsutil::resolve(path, follow_last, |path| self.link_at(path))
+    }
+
+    /// Keeps what a dry run would make of `path`, along with its content
+    /// before the run the first time it changes.
+    fn change(&self, path: &Path, after: Pending) -> Result<(), Error> {
+        let preview = self.preview.as_ref().expect("only called in dry runs");
+        if let Some(change) = preview
+            .borrow_mut()
+            .changes
+            .iter_mut()
+            .find(|change| change.path == path)
+        {
+            change.after = after;
+            return Ok(());
+        }
+
+        let before = self.read(path)?;
+        preview.borrow_mut().changes.push(Change {
+            path: path.to_path_buf(),
+            before,
+            after,
+        });
+        Ok(())
+    }
+
+    /// Runs a command to completion, failing if it cannot be started or
+    /// exits with a non-zero status. A dry run only records it and succeeds
+    /// with no output.
+    pub fn run(&self, program: &str, args: &[&str]) -> Result<Output, Error> {
+        if let Some(preview) = &self.preview {
+            preview
+                .borrow_mut()
+                .commands
+                .push(command_line(program, args));
+            return Ok(success());
+        }
+        self.query(program, args)
+    }
+
+    /// Runs a command which only reads the state of the machine, as
+    /// [`System::run`] does but even in a dry run. Fails when the machine
+    /// is not the one running, which cannot be asked.
+    pub fn query(&self, program: &str, args: &[&str]) -> Result<Output, Error> {
+        if !self.running {
+            return Err(Error::Unsupported(format!(
+                "`{}` cannot tell about a system below {} which is not running",
+                command_line(program, args),
+                self.root.display()
+            )));
+        }
+        let executable = match &self.bin_dir {
+            Some(bin_dir) if bin_dir.join(program).exists() => bin_dir.join(program),
+            _ => PathBuf::from(program),
+        };
+        let command_line = command_line(program, args);
+
+        let output = Command::new(executable)
+            .args(args)
+            .output()
+            .map_err(|err| match err.kind() {
+                ErrorKind::NotFound => Error::BackendMissing {
+                    program: program.to_string(),
+                    hint: None,
+                },
+                _ => Error::Io(io::Error::new(
+                    err.kind(),
+                    format!("`{}` failed: {}", command_line, err),
+                )),
+            })?;
+
+        if !output.status.success() {
+            return Err(Error::CommandFailed {
+                command: command_line,
+                status: output.status,
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+        Ok(output)
+    }
+}
+
+/// `program` and `args` separated by spaces, as a shell would take them.
+fn command_line(program: &str, args: &[&str]) -> String {
+    let mut words = vec![program];
+    words.extend(args);
+    words.join(" ")
+}
+
+/// The output of a command which was not run.
+fn success() -> Output {
+    Output {
+        status: ExitStatus::from_raw(0),
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    }
+}
+
+fn write_failed(path: &Path, err: io::Error) -> Error {
+    Error::write_failed(path.to_path_buf(), err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, TempDir};
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn paths_are_looked_up_below_the_root() -> Result<(), Error> {
+        let root = TempDir::new("system-root");
+        let system = System::with_root(root.path(), None);
+
+        system.write("/etc/systemd/resolved.conf.d/test.conf", "[Resolve]\n")?;
+
+        assert_eq!(
+            fs::read_to_string(root.path().join("etc/systemd/resolved.conf.d/test.conf"))?,
+            "[Resolve]\n"
+        );
+        assert_eq!(
+            system.read_dir("/etc/systemd/resolved.conf.d")?,
+            vec![PathBuf::from("/etc/systemd/resolved.conf.d/test.conf")]
+        );
+        assert!(system.read_dir("/etc/missing")?.is_empty());
+        assert!(system.remove("/etc/systemd/resolved.conf.d/test.conf")?);
+        assert!(!system.remove("/etc/systemd/resolved.conf.d/test.conf")?);
+        assert_eq!(system.read("/etc/systemd/resolved.conf.d/test.conf")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn commands_are_looked_up_in_the_bin_dir_first() -> Result<(), Error> {
+        let root = TempDir::new("system-run");
+        let bin_dir = testutil::fake_command(root.path(), "systemctl", "");
+        let system = System::with_root(root.path(), Some(&bin_dir));
+
+        system.run("systemctl", &["restart", "systemd-resolved"])?;
+
+        assert_eq!(
+            testutil::command_log(root.path(), "systemctl"),
+            "restart systemd-resolved\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn failing_and_missing_commands_are_errors() {
+        let root = TempDir::new("system-fail");
+        let bin_dir = testutil::fake_command(root.path(), "systemctl", "echo oops >&2; exit 1");
+        let system = System::with_root(root.path(), Some(&bin_dir));
+
+        let err = system.run("systemctl", &["restart"]).unwrap_err();
+        assert!(err.to_string().contains("oops"));
+        let err = system.run("cfg-adguard-dns-missing", &[]).unwrap_err();
+        assert!(matches!(err, Error::BackendMissing { .. }));
+    }
+
+    #[test]
+    fn symlinks_stay_below_the_root() -> Result<(), Error> {
+        let root = TempDir::new("system-symlinks");
+        let system = System::with_root(root.path(), None);
+        fs::create_dir_all(root.path().join("etc/resolvconf"))?;
+        system.symlink("/run/resolvconf/resolv.conf", "/etc/resolv.conf")?;
+        system.symlink("../../..", "/etc/resolvconf/run")?;
+
+        system.write("/etc/resolv.conf", "nameserver 9.9.9.9\n")?;
+
+        assert_eq!(
+            fs::read_to_string(root.path().join("run/resolvconf/resolv.conf"))?,
+            "nameserver 9.9.9.9\n"
+        );
+        assert_eq!(
+            system.read("/etc/resolvconf/run/run/resolvconf/resolv.conf")?,
+            system.read("/etc/resolv.conf")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn symlinks_in_parent_directories_stay_below_the_root() -> Result<(), Error> {
+        let root = TempDir::new("system-parents");
+        let system = System::with_root(root.path(), None);
+        fs::create_dir_all(root.path().join("etc"))?;
+        fs::create_dir_all(root.path().join("elsewhere"))?;
+        symlink("/elsewhere", root.path().join("etc/resolvconf"))?;
+
+        system.replace("/etc/resolvconf/head", "nameserver 9.9.9.9\n")?;
+        system.symlink("head", "/etc/resolvconf/link")?;
+
+        assert_eq!(
+            fs::read_to_string(root.path().join("elsewhere/head"))?,
+            "nameserver 9.9.9.9\n"
+        );
+        assert_eq!(
+            system.read_link("/etc/resolvconf/link")?,
+            Some(PathBuf::from("head"))
+        );
+        assert_eq!(
+            system.path("/etc/resolvconf/link")?,
+            root.path().join("elsewhere/head")
+        );
+        assert!(system.remove("/etc/resolvconf/link")?);
+        assert!(!root.path().join("elsewhere/link").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn services_of_an_alternate_root_are_not_reloaded() -> Result<(), Error> {
+        let root = TempDir::new("system-alternate-root");
+        let system = System::at_root(root.path());
+
+        system.transaction(|| system.reload("cfg-adguard-dns-missing", &["reload"]))?;
+
+        assert!(!system.is_live());
+        let err = system.query("nmcli", &["connection", "show"]).unwrap_err();
+        assert!(matches!(err, Error::Unsupported(_)));
+        let err = system.query("unbound-checkconf", &[]).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("`unbound-checkconf` cannot tell"));
+        Ok(())
+    }
+
+    #[test]
+    fn failed_rollback_is_part_of_the_error() -> Result<(), Error> {
+        let root = TempDir::new("system-rollback");
+        let bin_dir = testutil::fake_command(root.path(), "systemctl", "exit 1");
+        let system = System::with_root(root.path(), Some(&bin_dir));
+
+        let err = system
+            .transaction(|| {
+                system.write("/etc/dhcpcd.conf", "static\n")?;
+                system.reload("systemctl", &["reload-or-restart", "dnsmasq"])
+            })
+            .unwrap_err();
+
+        let Error::RollbackFailed { cause, rollback } = &err else {
+            panic!("not a failed rollback: {}", err);
+        };
+        assert!(matches!(**cause, Error::CommandFailed { .. }));
+        assert!(rollback.to_string().starts_with("rolling back failed: "));
+        assert_eq!(err.exit_code(), 6);
+        assert!(!root.path().join("etc/dhcpcd.conf").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn transaction_in_progress_elsewhere_is_busy() -> Result<(), Error> {
+        let root = TempDir::new("system-busy");
+        let system = System::with_root(root.path(), None);
+        let _journal = Journal::create(&system.journal_dir()?)?;
+
+        let err = system
+            .transaction(|| system.write("/etc/dhcpcd.conf", "static\n"))
+            .unwrap_err();
+
+        assert!(matches!(err, Error::Busy { pid: Some(pid) } if pid == process::id()));
+        assert_eq!(err.exit_code(), 8);
+        assert!(!root.path().join("etc/dhcpcd.conf").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn dry_run_leaves_the_machine_untouched() -> Result<(), Error> {
+        let root = TempDir::new("system-dry-run");
+        let bin_dir = testutil::fake_command(root.path(), "resolvconf", "");
+        System::with_root(root.path(), None).write("/etc/resolvconf/head", "old\n")?;
+        let system = System::with_root(root.path(), Some(&bin_dir)).dry_run();
+
+        system.transaction(|| {
+            system.write("/etc/resolvconf/head", "new\n")?;
+            system.write("/etc/resolvconf/head", "newer\n")?;
+            system.symlink("/etc/resolvconf/head", "/etc/resolv.conf")?;
+            system.write("/etc/dnsmasq.d/cfg-adguard-dns.conf", "no-resolv\n")?;
+            system.reload("resolvconf", &["-u"])
+        })?;
+
+        assert_eq!(system.read("/etc/resolv.conf")?.unwrap(), "newer\n");
+        assert_eq!(
+            system.read_dir("/etc/dnsmasq.d")?,
+            [PathBuf::from("/etc/dnsmasq.d/cfg-adguard-dns.conf")]
+        );
+        assert!(system.remove("/etc/dnsmasq.d/cfg-adguard-dns.conf")?);
+        assert!(system.read_dir("/etc/dnsmasq.d")?.is_empty());
+
+        let preview = system.preview().unwrap();
+        assert_eq!(
+            preview.changes[0],
+            Change {
+                path: PathBuf::from("/etc/resolvconf/head"),
+                before: Some(String::from("old\n")),
+                after: Pending::File(String::from("newer\n")),
+            }
+        );
+        assert_eq!(
+            preview.changes[1].after,
+            Pending::Symlink(PathBuf::from("/etc/resolvconf/head"))
+        );
+        assert_eq!(preview.changes[2].after, Pending::Absent);
+        assert_eq!(preview.commands, ["resolvconf -u"]);
+
+        assert_eq!(
+            fs::read_to_string(root.path().join("etc/resolvconf/head"))?,
+            "old\n"
+        );
+        assert!(!root.path().join("etc/resolv.conf").exists());
+        assert!(!root.path().join("etc/dnsmasq.d").exists());
+        assert!(!root.path().join("resolvconf.log").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn failed_transaction_is_rolled_back() -> Result<(), Error> {
+        let root = TempDir::new("system-transaction");
+        let bin_dir = testutil::fake_command(root.path(), "systemctl", "");
+        let system = System::with_root(root.path(), Some(&bin_dir));
+        system.write("/etc/dhcpcd.conf", "hostname\n")?;
+
+        let err = system
+            .transaction(|| {
+                system.write("/etc/dhcpcd.conf", "hostname\nstatic\n")?;
+                system.write("/etc/dnsmasq.d/cfg-adguard-dns.conf", "no-resolv\n")?;
+                system.reload("systemctl", &["reload-or-restart", "dnsmasq"])?;
+                Err::<(), _>(Error::Io(io::Error::other("reload failed")))
+            })
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "reload failed");
+        assert_eq!(system.read("/etc/dhcpcd.conf")?.unwrap(), "hostname\n");
+        assert!(!root.path().join("etc/dnsmasq.d").exists());
+        assert_eq!(
+            testutil::command_log(root.path(), "systemctl"),
+            "reload-or-restart dnsmasq\nreload-or-restart dnsmasq\n"
+        );
+        assert!(!system.recover()?);
+
+        system.transaction(|| system.write("/etc/dhcpcd.conf", "static\n"))?;
+
+        assert_eq!(system.read("/etc/dhcpcd.conf")?.unwrap(), "static\n");
+        assert!(!system.recover()?);
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::provider::{self, Profile};
+
+/// A directory under the system temp dir, removed with its content on drop.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = env::temp_dir().join(format!("cfg-adguard-dns-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("failed to create temp dir");
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The default profile of the provider named `name`.
+pub fn profile(name: &str) -> Profile {
+    Profile::new(provider::find(name).expect("unknown provider"))
+}
+
+/// Installs a fake `name` command in `<dir>/bin`, returning that directory.
+/// Each call appends its arguments to `<dir>/<name>.log` before running `script`.
+pub fn fake_command(dir: &Path, name: &str, script: &str) -> PathBuf {
+    let bin_dir = dir.join("bin");
+    fs::create_dir_all(&bin_dir).expect("failed to create bin dir");
+
+    let path = bin_dir.join(name);
+    let log = dir.join(format!("{}.log", name));
+    fs::write(
+        &path,
+        format!(
+            "#!/bin/sh\necho \"$@\" >> '{}'\n{}\n",
+            log.display(),
+            script
+        ),
+    )
+    .expect("failed to write fake command");
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+        .expect("failed to make fake command executable");
+
+    bin_dir
+}
+
+/// The arguments each call of the fake `name` command received, one call per line.
+pub fn command_log(dir: &Path, name: &str) -> String {
+    fs::read_to_string(dir.join(format!("{}.log", name))).unwrap_or_default()
+}
@@ -0,0 +1,158 @@
+//! Runs the binary against a system below a temp directory with `--root`,
+//! as when building an image.
+
+use std::env;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output};
+
+const HEAD_PATH: &str = "etc/resolvconf/resolv.conf.d/head";
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let path = env::temp_dir().join(format!("cfg-adguard-dns-it-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn run(root: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_cfg-adguard-dns"))
+        .arg("--root")
+        .arg(root)
+        .args(args)
+        .env_remove("CFG_ADGUARD_DNS_STATE_DIR")
+        .env_remove("RESOLVCONF_HEAD_PATH")
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/// A Debian root file system with resolvconf, whose resolv.conf points at
+/// a file generated at boot, absent for now.
+fn debian_root(name: &str) -> TempDir {
+    let root = TempDir::new(name);
+    fs::create_dir_all(root.0.join("etc/resolvconf/resolv.conf.d")).unwrap();
+    fs::write(root.0.join(HEAD_PATH), "# Local additions\n").unwrap();
+    symlink(
+        "/run/resolvconf/resolv.conf",
+        root.0.join("etc/resolv.conf"),
+    )
+    .unwrap();
+    root
+}
+
+#[test]
+fn activate_and_deactivate_below_a_root() {
+    let root = debian_root("activate");
+
+    let output = stdout(&run(&root.0, &["activate", "--provider", "quad9"]));
+
+    assert!(
+        output.contains("Detected backend: resolvconf"),
+        "{}",
+        output
+    );
+    let saved = output.find("Saved backup").expect("no backup note");
+    assert!(saved < output.find("successfully activated").unwrap());
+    let head = fs::read_to_string(root.0.join(HEAD_PATH)).unwrap();
+    assert!(head.starts_with("# Local additions\n"));
+    assert!(head.contains("nameserver 9.9.9.9\n"));
+    assert!(root.0.join("var/lib/cfg-adguard-dns/backups").is_dir());
+    assert!(!root.0.join("var/lib/cfg-adguard-dns/journal").exists());
+    assert!(stdout(&run(&root.0, &["status"])).contains("Quad9"));
+
+    stdout(&run(&root.0, &["deactivate"]));
+
+    assert_eq!(
+        fs::read_to_string(root.0.join(HEAD_PATH)).unwrap(),
+        "# Local additions\n"
+    );
+}
+
+#[test]
+fn head_file_of_the_previous_version_is_deactivated() {
+    let root = debian_root("legacy");
+    // What versions without a managed block appended.
+    let legacy = "# Local additions\n \n# AdGuard DNS \n# https://adguard-dns.com/en/public-dns.html\nnameserver 94.140.14.14\nnameserver 94.149.15.15\n";
+    fs::write(root.0.join(HEAD_PATH), legacy).unwrap();
+
+    let status = stdout(&run(&root.0, &["status"]));
+    assert!(status.contains("94.149.15.15"), "{}", status);
+
+    let output = stdout(&run(&root.0, &["deactivate"]));
+
+    assert!(output.contains("deactivated from resolvconf"), "{}", output);
+    assert_eq!(
+        fs::read_to_string(root.0.join(HEAD_PATH)).unwrap(),
+        "# Local additions\n"
+    );
+}
+
+#[test]
+fn dry_run_below_a_root_lists_no_reload() {
+    let root = debian_root("dry-run");
+
+    let output = stdout(&run(&root.0, &["activate", "--dry-run"]));
+
+    assert!(output.contains("+nameserver 94.140.14.14\n"), "{}", output);
+    assert!(!output.contains("Would run:"), "{}", output);
+    assert_eq!(
+        fs::read_to_string(root.0.join(HEAD_PATH)).unwrap(),
+        "# Local additions\n"
+    );
+    assert!(!root.0.join("var").exists());
+}
+
+#[test]
+fn forwarders_below_a_root_are_not_checked() {
+    let root = debian_root("forwarder");
+
+    stdout(&run(&root.0, &["activate", "--backend", "unbound"]));
+
+    let conf = root
+        .0
+        .join("etc/unbound/unbound.conf.d/cfg-adguard-dns.conf");
+    assert!(fs::read_to_string(conf)
+        .unwrap()
+        .contains("forward-addr: 94.140.14.14\n"));
+    assert!(!root.0.join("var").exists());
+}
+
+#[test]
+fn failed_run_below_a_root_leaves_no_state() {
+    let root = debian_root("failed");
+
+    let output = run(&root.0, &["activate", "--backend", "networkmanager"]);
+
+    assert_eq!(output.status.code(), Some(5));
+    assert!(!root.0.join("var").exists());
+}
+
+#[test]
+fn missing_root_is_a_usage_error() {
+    let root = TempDir::new("missing");
+
+    let output = run(&root.0.join("missing"), &["status"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(run(&root.0, &["proxy"]).status.code(), Some(2));
+}